@@ -16,6 +16,7 @@ fn main() {
     // of 16000 USD/BTC is we short $100 at the price of (1/16000) BTC/USD.
     // That is what the exchange actually using in its formula when it calculates
     // your total position as well as your profit.
+    assert_eq!(inst.display_pair(), "USD/BTC");
 
     // We can represent this case directly by using `Reversed`:
     let mut p = inst.position(Reversed((dec!(16000), dec!(100))));
@@ -55,7 +55,7 @@ fn main() -> anyhow::Result<()> {
     // we need to decide the "root asset" (or the "unit asset") first.
     // We will choose `USDT` as our root asset.
     for inst in expr.instruments(&usdt) {
-        println!("{inst}");
+        println!("{inst:#}");
     }
 
     // To evaluate the equity of our positions, we must provide the prices
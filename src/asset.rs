@@ -1,4 +1,4 @@
-use alloc::{fmt, string::String};
+use alloc::{fmt, format, string::String};
 use core::{borrow::Borrow, hash::Hash, ops::Deref, str::FromStr};
 use smol_str::SmolStr as Str;
 
@@ -101,12 +101,53 @@ impl Borrow<str> for Asset {
     }
 }
 
-impl<'a> Borrow<str> for &'a Asset {
+impl Borrow<str> for &Asset {
     fn borrow(&self) -> &str {
         self.as_str()
     }
 }
 
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Asset {
+    /// Generate a valid asset of 1 to 8 uppercase letters and digits.
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+        let len = u.int_in_range(1..=8)?;
+        let mut inner = String::with_capacity(len);
+        for _ in 0..len {
+            inner.push(char::from(*u.choose(CHARS)?));
+        }
+        Ok(Self {
+            inner: Str::new(inner),
+        })
+    }
+}
+
+/// The ISO 4217 numeric codes of the major fiat currencies.
+#[cfg(feature = "iso4217")]
+const ISO_CODES: &[(&str, u16)] = &[
+    ("AUD", 36),
+    ("BRL", 986),
+    ("CAD", 124),
+    ("CHF", 756),
+    ("CNY", 156),
+    ("EUR", 978),
+    ("GBP", 826),
+    ("HKD", 344),
+    ("INR", 356),
+    ("JPY", 392),
+    ("KRW", 410),
+    ("MXN", 484),
+    ("NOK", 578),
+    ("NZD", 554),
+    ("RUB", 643),
+    ("SEK", 752),
+    ("SGD", 702),
+    ("TRY", 949),
+    ("USD", 840),
+    ("ZAR", 710),
+];
+
 impl Asset {
     /// The delimiter of assets in the spot format.
     pub const SEP: char = '-';
@@ -155,6 +196,72 @@ impl Asset {
         self.inner.as_str()
     }
 
+    /// Get the length of the asset in bytes.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    /// Is empty. Always `false` for a valid asset.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    /// Create an iterator of the chars of the asset.
+    #[inline]
+    pub fn chars(&self) -> core::str::Chars<'_> {
+        self.inner.chars()
+    }
+
+    /// Append the `suffix` to the asset, e.g. `BTC` with `.E` becomes `BTC.E`.
+    /// The result is validated in the same way as parsing,
+    /// and the asset is unchanged if it is not valid.
+    pub fn try_push_suffix(&mut self, suffix: &str) -> Result<(), ParseAssetError> {
+        *self = Self::try_from(format!("{}{suffix}", self.inner).as_str())?;
+        Ok(())
+    }
+
+    /// Prepend the `prefix` to the asset, e.g. `BTC` with `W` becomes `WBTC`.
+    /// The result is validated in the same way as parsing,
+    /// and the asset is unchanged if it is not valid.
+    pub fn try_push_prefix(&mut self, prefix: &str) -> Result<(), ParseAssetError> {
+        *self = Self::try_from(format!("{prefix}{}", self.inner).as_str())?;
+        Ok(())
+    }
+
+    /// Join the assets into the spot format, e.g. `BTC-USDT`.
+    pub fn join_pair(base: &Asset, quote: &Asset) -> String {
+        format!("{base}{}{quote}", Self::SEP)
+    }
+
+    /// Split the str in the spot format into the base and quote assets.
+    /// Return [`None`] if the format is not valid.
+    pub fn split_pair(s: &str) -> Option<(Asset, Asset)> {
+        let (base, quote) = s.split_once(Self::SEP)?;
+        Some((base.parse().ok()?, quote.parse().ok()?))
+    }
+
+    /// Get the ISO 4217 numeric code of the asset, e.g. `840` for `USD`.
+    /// Return [`None`] if the asset is not one of the built-in major fiat currencies.
+    #[cfg(feature = "iso4217")]
+    pub fn iso_code(&self) -> Option<u16> {
+        ISO_CODES
+            .iter()
+            .find(|(asset, _)| *asset == self.as_str())
+            .map(|(_, code)| *code)
+    }
+
+    /// Get the asset of the ISO 4217 numeric code, e.g. `USD` for `840`.
+    /// Return [`None`] if the code is not one of the built-in major fiat currencies.
+    #[cfg(feature = "iso4217")]
+    pub fn from_iso_code(code: u16) -> Option<Self> {
+        ISO_CODES
+            .iter()
+            .find(|(_, c)| *c == code)
+            .map(|(asset, _)| Self::new_inline(asset))
+    }
+
     /// Create a [`Positions`] with only value of this asset.
     pub fn value<T>(&self, value: T) -> Positions<T>
     where
@@ -203,10 +310,50 @@ mod tests {
         assert_eq!(asset, String::from("uSdt"));
     }
 
+    #[test]
+    fn push() {
+        let mut asset = Asset::BTC;
+        assert_eq!(asset.len(), 3);
+        assert!(!asset.is_empty());
+        asset.try_push_prefix("w").unwrap();
+        assert_eq!(asset, "WBTC");
+        assert!(asset.try_push_suffix("-USDT").is_err());
+        assert!(asset.try_push_suffix("₿").is_err());
+        assert_eq!(asset, "WBTC");
+        asset.try_push_suffix(".e").unwrap();
+        assert_eq!(asset.as_str(), "WBTC.E");
+        assert_eq!(asset.chars().next(), Some('W'));
+    }
+
+    #[test]
+    fn pair() {
+        let s = Asset::join_pair(&Asset::BTC, &Asset::USDT);
+        assert_eq!(s, "BTC-USDT");
+        assert_eq!(
+            Asset::split_pair("btc-usdt"),
+            Some((Asset::BTC, Asset::USDT))
+        );
+        assert_eq!(Asset::split_pair("BTCUSDT"), None);
+        assert_eq!(Asset::split_pair("BTC-USDT-SWAP"), None);
+    }
+
+    #[cfg(feature = "iso4217")]
+    #[test]
+    fn iso_code() {
+        assert_eq!(Asset::USD.iso_code(), Some(840));
+        assert_eq!(Asset::from_str("eur").unwrap().iso_code(), Some(978));
+        assert_eq!(Asset::USDT.iso_code(), None);
+        assert_eq!(
+            Asset::from_iso_code(392),
+            Some(Asset::from_str("JPY").unwrap())
+        );
+        assert_eq!(Asset::from_iso_code(0), None);
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn serde() -> anyhow::Result<()> {
-        use alloc::{vec, vec::Vec};
+        use alloc::vec::Vec;
 
         let value = serde_json::json!(["usdt", "BTC"]);
         let assets: Vec<Asset> = serde_json::from_value(value)?;
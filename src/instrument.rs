@@ -2,13 +2,16 @@ use core::{borrow::Borrow, hash::Hash, str::FromStr};
 
 use crate::{
     asset::{Asset, ParseAssetError},
-    prelude::Str,
+    prelude::{HashMap, Str},
     IntoNaivePosition, Position, PositionNum,
 };
 use alloc::fmt;
 
 #[cfg(not(feature = "std"))]
-use alloc::string::{String, ToString};
+use alloc::{
+    format,
+    string::{String, ToString},
+};
 
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
@@ -21,6 +24,21 @@ pub struct Instrument {
     symbol: Symbol,
     base: Asset,
     quote: Asset,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    index: Option<Asset>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    lot_size: Option<Str>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    min_size: Option<Str>,
 }
 
 impl Instrument {
@@ -31,17 +49,40 @@ impl Instrument {
     }
 
     /// Create a new spot.
+    /// # Warning
+    /// The `base` and `quote` assets should be distinct, see [`Instrument::try_spot`].
     pub fn spot(base: &Asset, quote: &Asset) -> Self {
         Self {
             prefer_reversed: false,
             symbol: Symbol::spot(base, quote),
             base: base.clone(),
             quote: quote.clone(),
+            index: None,
+            lot_size: None,
+            min_size: None,
         }
     }
 
+    /// Create a new reversed-prefering spot,
+    /// the same as `Instrument::spot(base, quote).prefer_reversed(true)`.
+    /// # Warning
+    /// The `base` and `quote` assets should be distinct, see [`Instrument::try_spot`].
+    pub fn reversed_spot(base: &Asset, quote: &Asset) -> Self {
+        Self::spot(base, quote).prefer_reversed(true)
+    }
+
+    /// Create a new spot.
+    /// Return [`ParseSymbolError::SameBaseQuote`] if `base` equals `quote`.
+    pub fn try_spot(base: &Asset, quote: &Asset) -> Result<Self, ParseSymbolError> {
+        if base == quote {
+            return Err(ParseSymbolError::SameBaseQuote);
+        }
+        Ok(Self::spot(base, quote))
+    }
+
     /// Create a new derivative.
-    /// Return [`ParseSymbolError`] if the `prefix` is not valid.
+    /// Return [`ParseSymbolError`] if the `prefix` is not valid
+    /// or `base` equals `quote`.
     /// # Warning
     /// User must make sure that `inst1.symbol == inst2.symbol`
     /// only if `inst1.base == inst2.base && inst1.quote == inst2.quote`.
@@ -51,17 +92,52 @@ impl Instrument {
         base: &Asset,
         quote: &Asset,
     ) -> Result<Self, ParseSymbolError> {
+        if base == quote {
+            return Err(ParseSymbolError::SameBaseQuote);
+        }
         let symbol = Symbol::derivative(prefix, symbol)?;
         Ok(Self {
             prefer_reversed: false,
             symbol,
             base: base.clone(),
             quote: quote.clone(),
+            index: None,
+            lot_size: None,
+            min_size: None,
         })
     }
 
+    /// Create a new reversed-prefering derivative, e.g. a coin-margined swap,
+    /// the same as [`Instrument::derivative`] followed by `prefer_reversed(true)`.
+    /// Return [`ParseSymbolError`] if the `prefix` is not valid
+    /// or `base` equals `quote`.
+    pub fn reversed_derivative(
+        prefix: &str,
+        symbol: &str,
+        base: &Asset,
+        quote: &Asset,
+    ) -> Result<Self, ParseSymbolError> {
+        Ok(Self::derivative(prefix, symbol, base, quote)?.prefer_reversed(true))
+    }
+
+    /// Get the underlying spot market, e.g. `BTC-USDT` for `SWAP:BTC-USDT-SWAP`.
+    ///
+    /// For a reversed-prefering instrument, the spot is of the reversed pair and
+    /// is not reversed-prefering itself, e.g. `BTC-USD` for the coin-margined
+    /// `SWAP:BTC-USD-SWAP` (whose base is `USD`), so that its prices are in the
+    /// same form as the prices shown for the instrument.
+    /// The quanto index and the lot size are not kept.
+    pub fn to_spot(&self) -> Self {
+        if self.prefer_reversed {
+            Self::spot(&self.quote, &self.base)
+        } else {
+            Self::spot(&self.base, &self.quote)
+        }
+    }
+
     /// Convert to the revsered spot.
     /// Return [`None`] if it is not a spot.
+    /// The lot size is not kept since it is in the units of the base asset.
     pub fn to_reversed_spot(&self) -> Option<Self> {
         let symbol = self.symbol.to_reversed_symbol()?;
         Some(Self {
@@ -69,11 +145,15 @@ impl Instrument {
             symbol,
             base: self.quote.clone(),
             quote: self.base.clone(),
+            index: self.index.clone(),
+            lot_size: None,
+            min_size: None,
         })
     }
 
     /// Create a new instrument with the given symbol.
-    /// Return [`ParseSymbolError`] if the `symbol` does not match the given `base` or `quote`.
+    /// Return [`ParseSymbolError`] if the `symbol` does not match the given `base` or `quote`,
+    /// or `base` equals `quote`.
     /// # Warning
     /// User must make sure that `inst1.symbol == inst2.symbol`
     /// only if `inst1.base == inst2.base && inst1.quote == inst2.quote`.
@@ -82,6 +162,9 @@ impl Instrument {
         base: &Asset,
         quote: &Asset,
     ) -> Result<Self, ParseSymbolError> {
+        if base == quote {
+            return Err(ParseSymbolError::SameBaseQuote);
+        }
         if let Some(pair) = symbol.as_spot() {
             if pair != (base, quote) {
                 return Err(ParseSymbolError::InvalidSpotFormat);
@@ -92,6 +175,22 @@ impl Instrument {
             symbol,
             base: base.clone(),
             quote: quote.clone(),
+            index: None,
+            lot_size: None,
+            min_size: None,
+        })
+    }
+
+    /// Return a clone of the instrument with the symbol replaced by the given one,
+    /// e.g. to attach a venue-specific symbol to the same market.
+    /// The assets, the reversed preference and the other metadata are preserved.
+    /// Return [`ParseSymbolError`] if the `symbol` does not match the assets,
+    /// in the same way as [`Instrument::try_with_symbol`].
+    pub fn with_symbol(&self, symbol: Symbol) -> Result<Instrument, ParseSymbolError> {
+        let inst = Self::try_with_symbol(symbol, &self.base, &self.quote)?;
+        Ok(Self {
+            symbol: inst.symbol,
+            ..self.clone()
         })
     }
 
@@ -108,6 +207,103 @@ impl Instrument {
         self.prefer_reversed
     }
 
+    /// Is this an inverse (coin-margined) instrument.
+    ///
+    /// Since the quote asset is always the settlement asset in this crate,
+    /// this is based on the reversed preference: inverse instruments are
+    /// expected to be declared with the base and quote swapped and marked
+    /// as reversed-prefering (e.g. `BTC-USD-SWAP` with base `USD` and quote `BTC`).
+    /// Quanto instruments are neither inverse nor linear.
+    #[inline]
+    pub fn is_inverse(&self) -> bool {
+        self.prefer_reversed && !self.is_quanto()
+    }
+
+    /// Is this a linear instrument, whose profit is settled in the asset its price is quoted in.
+    ///
+    /// See [`Instrument::is_inverse`] for the limitation.
+    #[inline]
+    pub fn is_linear(&self) -> bool {
+        !self.prefer_reversed && !self.is_quanto()
+    }
+
+    /// Mark this instrument as a quanto instrument,
+    /// whose price is quoted in the `index` asset
+    /// but settled in the `quote` asset.
+    ///
+    /// The value of a position of a quanto instrument is denominated in the `index` asset,
+    /// and will be converted to the `quote` asset with the price of `index-quote` when evaluating.
+    pub fn quanto(mut self, index: &Asset) -> Self {
+        self.index = Some(index.clone());
+        self
+    }
+
+    /// Get the index asset of the quanto instrument.
+    /// Return [`None`] if it is not a quanto instrument.
+    pub fn quanto_index(&self) -> Option<&Asset> {
+        self.index.as_ref()
+    }
+
+    /// Is quanto.
+    #[inline]
+    pub fn is_quanto(&self) -> bool {
+        self.index.is_some()
+    }
+
+    /// Set the lot size (the step of the order sizes) of the instrument, e.g. `"0.001"`.
+    ///
+    /// It is stored as a str since the instrument is not generic over the numeric type,
+    /// and is parsed when used, see [`Instrument::round_size`].
+    /// Return [`ParseLotSizeError`] if it is not a positive decimal number.
+    pub fn with_lot_size(mut self, lot_size: &str) -> Result<Self, ParseLotSizeError> {
+        self.lot_size = Some(parse_lot_size(lot_size)?);
+        Ok(self)
+    }
+
+    /// Get the lot size of the instrument.
+    pub fn lot_size(&self) -> Option<&str> {
+        self.lot_size.as_deref()
+    }
+
+    /// Set the minimum order size of the instrument, e.g. `"0.01"`,
+    /// stored in the same way as the lot size (see [`Instrument::with_lot_size`]).
+    /// Return [`ParseLotSizeError`] if it is not a positive decimal number.
+    pub fn with_min_size(mut self, min_size: &str) -> Result<Self, ParseLotSizeError> {
+        self.min_size = Some(parse_lot_size(min_size)?);
+        Ok(self)
+    }
+
+    /// Get the minimum order size of the instrument.
+    pub fn min_size(&self) -> Option<&str> {
+        self.min_size.as_deref()
+    }
+
+    /// Round the given size toward zero to a multiple of the lot size,
+    /// and to zero if its absolute value is then less than the minimum order size.
+    /// Return the size unchanged if there is neither lot size nor minimum order size.
+    ///
+    /// Return [`None`] if the lot size or the minimum order size cannot be
+    /// represented as a positive `T`, e.g. `"0.5"` for an integer `T`.
+    pub fn round_size<T>(&self, size: &T) -> Option<T>
+    where
+        T: PositionNum + FromStr,
+    {
+        let parse = |s: &str| s.parse::<T>().ok().filter(|x| *x > T::zero());
+        let mut size = size.clone();
+        if let Some(lot_size) = self.lot_size.as_deref() {
+            let lot_size = parse(lot_size)?;
+            let mut rem = size.clone();
+            rem %= &lot_size;
+            size -= rem;
+        }
+        if let Some(min_size) = self.min_size.as_deref() {
+            if size.abs() < parse(min_size)? {
+                size = T::zero();
+            }
+        }
+        Some(size)
+    }
+
     /// Get the symbol.
     #[inline]
     pub fn as_symbol(&self) -> &Symbol {
@@ -126,6 +322,13 @@ impl Instrument {
         self.symbol.is_derivative()
     }
 
+    /// Whether the instruments are of the same market,
+    /// see [`Symbol::is_same_market`].
+    #[inline]
+    pub fn is_same_market(&self, other: &Self) -> bool {
+        self.symbol.is_same_market(&other.symbol)
+    }
+
     /// Get the base asset.
     pub fn base(&self) -> &Asset {
         &self.base
@@ -136,6 +339,60 @@ impl Instrument {
         &self.quote
     }
 
+    /// Return the instrument with the asset `from` replaced by `to`.
+    /// The symbol is rebuilt for spots, while the symbol of a derivative is kept.
+    pub(crate) fn with_renamed_asset(&self, from: &Asset, to: &Asset) -> Self {
+        let rename = |asset: &Asset| {
+            if asset == from {
+                to.clone()
+            } else {
+                asset.clone()
+            }
+        };
+        let base = rename(&self.base);
+        let quote = rename(&self.quote);
+        let symbol = if self.symbol.is_spot() {
+            Symbol::spot(&base, &quote)
+        } else {
+            self.symbol.clone()
+        };
+        Self {
+            prefer_reversed: self.prefer_reversed,
+            symbol,
+            base,
+            quote,
+            index: self.index.as_ref().map(rename),
+            lot_size: self.lot_size.clone(),
+            min_size: self.min_size.clone(),
+        }
+    }
+
+    /// Format the base and quote assets as `base/quote`,
+    /// e.g. `USD/BTC` for the coin-margin `BTC-USD-SWAP`.
+    pub fn display_pair(&self) -> String {
+        format!("{}/{}", self.base, self.quote)
+    }
+
+    /// Create a [`Position`] of this instrument at the given price with the size
+    /// of the given `notional` value, which is denominated in the `quote` asset
+    /// as [`Position::notional_value`] is.
+    ///
+    /// The `price` is treated to be in the reversed-form if the instrument is
+    /// reversed-prefering, e.g. `16000` for a coin-margined `BTC-USD-SWAP`.
+    /// Return [`None`] if the `price` is zero.
+    pub fn position_from_notional<T>(&self, price: T, notional: T) -> Option<Position<T>>
+    where
+        T: PositionNum,
+    {
+        let price = if self.prefer_reversed {
+            price.checked_reciprocal()?
+        } else {
+            price
+        };
+        let naive = crate::NaivePosition::from_notional(price, notional)?;
+        Some(self.position(naive))
+    }
+
     /// Create a [`Position`] with the given position of this instrument.
     #[inline]
     pub fn position<T, P>(&self, position: P) -> Position<T>
@@ -147,15 +404,128 @@ impl Instrument {
     }
 }
 
+#[cfg(feature = "arbitrary")]
+impl Instrument {
+    /// The prefixes used by the generated derivatives.
+    const ARBITRARY_PREFIXES: [&'static str; 3] = ["SWAP", "FUTURES", "OPTION"];
+
+    /// Generate a symbol consistent with the given distinct assets,
+    /// either a spot or a derivative like `SWAP:BTC-USDT-XYZ`.
+    fn arbitrary_symbol(
+        u: &mut arbitrary::Unstructured<'_>,
+        base: &Asset,
+        quote: &Asset,
+    ) -> arbitrary::Result<Symbol> {
+        use arbitrary::Arbitrary;
+
+        if bool::arbitrary(u)? {
+            return Ok(Symbol::spot(base, quote));
+        }
+        let prefix = u.choose(&Self::ARBITRARY_PREFIXES)?;
+        let suffix = Asset::arbitrary(u)?;
+        Ok(
+            Symbol::derivative(prefix, &format!("{base}-{quote}-{suffix}"))
+                .expect("the prefix must be valid"),
+        )
+    }
+
+    /// Generate a pair of distinct assets.
+    fn arbitrary_pair(u: &mut arbitrary::Unstructured<'_>) -> arbitrary::Result<(Asset, Asset)> {
+        use arbitrary::Arbitrary;
+
+        let base = Asset::arbitrary(u)?;
+        let mut quote = Asset::arbitrary(u)?;
+        if quote == base {
+            quote
+                .try_push_suffix("X")
+                .expect("the suffix must be valid");
+        }
+        Ok((base, quote))
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Instrument {
+    /// Generate a valid instrument with distinct base and quote assets,
+    /// whose symbol is derived from the assets so that the symbols of
+    /// the generated instruments are equal only if their assets are equal.
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let (base, quote) = Self::arbitrary_pair(u)?;
+        let symbol = Self::arbitrary_symbol(u, &base, &quote)?;
+        let inst = Self::try_with_symbol(symbol, &base, &quote).expect("the symbol must be valid");
+        Ok(inst.prefer_reversed(bool::arbitrary(u)?))
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Symbol {
+    /// Generate a valid spot or derivative symbol.
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let (base, quote) = Instrument::arbitrary_pair(u)?;
+        Instrument::arbitrary_symbol(u, &base, &quote)
+    }
+}
+
+/// All the fields of an instrument in order, for the formats that are not
+/// self-describing, where the optional fields cannot be skipped.
+#[cfg(feature = "bincode")]
+#[derive(Serialize, Deserialize)]
+pub(crate) struct InstrumentRecord {
+    prefer_reversed: bool,
+    symbol: Symbol,
+    base: Asset,
+    quote: Asset,
+    index: Option<Asset>,
+    lot_size: Option<Str>,
+    min_size: Option<Str>,
+}
+
+#[cfg(feature = "bincode")]
+impl From<&Instrument> for InstrumentRecord {
+    fn from(inst: &Instrument) -> Self {
+        Self {
+            prefer_reversed: inst.prefer_reversed,
+            symbol: inst.symbol.clone(),
+            base: inst.base.clone(),
+            quote: inst.quote.clone(),
+            index: inst.index.clone(),
+            lot_size: inst.lot_size.clone(),
+            min_size: inst.min_size.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl From<InstrumentRecord> for Instrument {
+    fn from(record: InstrumentRecord) -> Self {
+        Self {
+            prefer_reversed: record.prefer_reversed,
+            symbol: record.symbol,
+            base: record.base,
+            quote: record.quote,
+            index: record.index,
+            lot_size: record.lot_size,
+            min_size: record.min_size,
+        }
+    }
+}
+
 impl From<(Asset, Asset)> for Instrument {
     fn from((base, quote): (Asset, Asset)) -> Self {
         Self::spot(&base, &quote)
     }
 }
 
+/// Display the symbol of the instrument.
+/// The alternate form (`{:#}`) appends `*` if the instrument is reversed-prefering,
+/// the same as the display of [`Position`](crate::Position).
 impl fmt::Display for Instrument {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.symbol)
+        write!(f, "{}", self.symbol)?;
+        if f.alternate() && self.is_prefer_reversed() {
+            write!(f, "*")?;
+        }
+        Ok(())
     }
 }
 
@@ -172,7 +542,7 @@ impl Eq for Instrument {}
 
 impl PartialOrd for Instrument {
     fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
-        self.symbol.partial_cmp(&other.symbol)
+        Some(self.cmp(other))
     }
 }
 
@@ -200,7 +570,7 @@ impl Borrow<Symbol> for Instrument {
     }
 }
 
-impl<'a> Borrow<Symbol> for &'a Instrument {
+impl Borrow<Symbol> for &Instrument {
     fn borrow(&self) -> &Symbol {
         &self.symbol
     }
@@ -281,11 +651,34 @@ impl Symbol {
         Some(Self::spot(quote, base))
     }
 
+    /// Whether the symbols are of the same market, where a spot and its
+    /// reversed spot (e.g. `BTC-USDT` and `USDT-BTC`) are treated as the same,
+    /// while the derivatives must be equal.
+    pub fn is_same_market(&self, other: &Self) -> bool {
+        match (self.as_spot(), other.as_spot()) {
+            (Some((base, quote)), Some(other)) => (base, quote) == other || (quote, base) == other,
+            _ => self == other,
+        }
+    }
+
     /// Create a derivative symbol.
     /// Return [`ParseSymbolError`] if the prefix is not valid.
     pub fn derivative(prefix: &str, symbol: &str) -> Result<Self, ParseSymbolError> {
         Ok(Self(Repr::derivative(prefix, symbol)?))
     }
+
+    /// Get the canonical form of the symbol, in which the prefix and the symbol
+    /// of a derivative are uppercased, so that the symbols of the same market
+    /// from different sources can be compared.
+    pub fn normalized(&self) -> Self {
+        match &self.0 {
+            Repr::Spot(_, _) => self.clone(),
+            Repr::Derivative(prefix, symbol) => Self(Repr::Derivative(
+                Str::new(prefix.to_ascii_uppercase()),
+                Str::new(symbol.to_ascii_uppercase()),
+            )),
+        }
+    }
 }
 
 /// The internal representation of a symbol.
@@ -355,6 +748,12 @@ pub enum ParseSymbolError {
     /// Asset errors.
     #[cfg_attr(feature = "thiserror", error("parse asset error: {0}"))]
     Asset(ParseAssetError),
+    /// The base and quote assets cannot be inferred.
+    #[cfg_attr(feature = "thiserror", error("missing base and quote assets"))]
+    MissingAssets,
+    /// The base and quote assets are the same.
+    #[cfg_attr(feature = "thiserror", error("the base and quote assets are the same"))]
+    SameBaseQuote,
 }
 
 #[cfg(not(feature = "thiserror"))]
@@ -364,10 +763,47 @@ impl fmt::Display for ParseSymbolError {
             Self::InvalidSpotFormat => write!(f, "invalid spot format"),
             Self::InvalidPrefix => write!(f, "invalid prefix"),
             Self::Asset(err) => write!(f, "parse asset error: {err}"),
+            Self::MissingAssets => write!(f, "missing base and quote assets"),
+            Self::SameBaseQuote => write!(f, "the base and quote assets are the same"),
+        }
+    }
+}
+
+/// Parse lot size error, see [`Instrument::with_lot_size`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+pub enum ParseLotSizeError {
+    /// Not a decimal number.
+    #[cfg_attr(feature = "thiserror", error("invalid decimal number"))]
+    Invalid,
+    /// The number is zero.
+    #[cfg_attr(feature = "thiserror", error("the size must be positive"))]
+    Zero,
+}
+
+#[cfg(not(feature = "thiserror"))]
+impl fmt::Display for ParseLotSizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid => write!(f, "invalid decimal number"),
+            Self::Zero => write!(f, "the size must be positive"),
         }
     }
 }
 
+/// Check that `s` is a positive decimal number, e.g. `1`, `0.001` or `100.5`.
+fn parse_lot_size(s: &str) -> Result<Str, ParseLotSizeError> {
+    let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+    let digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if int.is_empty() || !digits(int) || !digits(frac) || s.ends_with('.') {
+        return Err(ParseLotSizeError::Invalid);
+    }
+    if int.bytes().chain(frac.bytes()).all(|b| b == b'0') {
+        return Err(ParseLotSizeError::Zero);
+    }
+    Ok(Str::new(s))
+}
+
 impl From<ParseAssetError> for ParseSymbolError {
     fn from(err: ParseAssetError) -> Self {
         Self::Asset(err)
@@ -425,6 +861,63 @@ impl FromStr for Symbol {
     }
 }
 
+/// A cache of parsed instruments keyed by their symbol strings,
+/// to avoid parsing the same symbols repeatedly on the hot paths.
+///
+/// The stateless constructors (e.g. [`Instrument::try_new`]) are still the default,
+/// this is only an optional helper.
+#[derive(Debug, Clone, Default)]
+pub struct InstrumentCache {
+    instruments: HashMap<Str, Instrument>,
+}
+
+impl InstrumentCache {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the cached instrument of the `symbol` string,
+    /// or create it with [`Instrument::try_new`] and cache it if not found.
+    /// # Warning
+    /// The `base` and `quote` are only used for creating the instrument,
+    /// see [`Instrument::try_new`] for the uniqueness of symbols.
+    pub fn get_or_try_insert(
+        &mut self,
+        symbol: &str,
+        base: &Asset,
+        quote: &Asset,
+    ) -> Result<&Instrument, ParseSymbolError> {
+        if !self.instruments.contains_key(symbol) {
+            let inst = Instrument::try_new(symbol, base, quote)?;
+            self.instruments.insert(Str::new(symbol), inst);
+        }
+        let inst = &self.instruments[symbol];
+        debug_assert_eq!((&inst.base, &inst.quote), (base, quote));
+        Ok(inst)
+    }
+
+    /// Get the cached instrument of the `symbol` string.
+    pub fn get(&self, symbol: &str) -> Option<&Instrument> {
+        self.instruments.get(symbol)
+    }
+
+    /// Get the number of the cached instruments.
+    pub fn len(&self) -> usize {
+        self.instruments.len()
+    }
+
+    /// Is empty.
+    pub fn is_empty(&self) -> bool {
+        self.instruments.is_empty()
+    }
+
+    /// Remove all the cached instruments.
+    pub fn clear(&mut self) {
+        self.instruments.clear();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -441,6 +934,17 @@ mod tests {
         assert_eq!(spot.to_string(), "BTC-USDT");
     }
 
+    #[test]
+    fn normalized_symbol() {
+        let lhs = Symbol::from_str("swap:btc-usdt-swap").unwrap();
+        let rhs = Symbol::from_str("SWAP:BTC-USDT-SWAP").unwrap();
+        assert_ne!(lhs, rhs);
+        assert_eq!(lhs.normalized(), rhs.normalized());
+        assert_eq!(lhs.normalized(), rhs);
+        let spot = Symbol::from_str("btc-usdt").unwrap();
+        assert_eq!(spot.normalized(), spot);
+    }
+
     #[test]
     fn reversed_spot_symbol() {
         let spot: Symbol = "BTC-USDT".parse().unwrap();
@@ -459,10 +963,250 @@ mod tests {
         );
     }
 
+    #[test]
+    fn inverse_and_linear() {
+        let inverse = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::USD, &Asset::BTC)
+            .unwrap()
+            .prefer_reversed(true);
+        assert!(inverse.is_inverse());
+        assert!(!inverse.is_linear());
+        let linear = Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::BTC, &Asset::USDT).unwrap();
+        assert!(linear.is_linear());
+        assert!(!linear.is_inverse());
+        let quanto = Instrument::try_new("SWAP:ETH-USD-SWAP", &Asset::ETH, &Asset::BTC)
+            .unwrap()
+            .quanto(&Asset::USD);
+        assert!(!quanto.is_linear());
+        assert!(!quanto.is_inverse());
+    }
+
+    #[cfg(all(feature = "arbitrary", feature = "std"))]
+    #[test]
+    fn arbitrary_symbol_round_trip() -> anyhow::Result<()> {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let data = (0..4096)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect::<Vec<_>>();
+        let mut u = Unstructured::new(&data);
+        for _ in 0..64 {
+            let symbol = Symbol::arbitrary(&mut u)?;
+            assert_eq!(Symbol::from_str(&symbol.to_string())?, symbol);
+            let inst = Instrument::arbitrary(&mut u)?;
+            assert_ne!(inst.base(), inst.quote());
+            let parsed = Instrument::try_with_symbol(
+                Symbol::from_str(&inst.as_symbol().to_string())?,
+                inst.base(),
+                inst.quote(),
+            )?;
+            assert_eq!(parsed, inst);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn with_symbol() {
+        let swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::USD, &Asset::BTC)
+            .unwrap()
+            .prefer_reversed(true)
+            .with_lot_size("100")
+            .unwrap();
+        let renamed = swap
+            .with_symbol(Symbol::derivative("PERP", "BTCUSD").unwrap())
+            .unwrap();
+        assert_eq!(renamed.as_symbol().to_string(), "PERP:BTCUSD");
+        assert_eq!(renamed.base(), &Asset::USD);
+        assert_eq!(renamed.quote(), &Asset::BTC);
+        assert!(renamed.is_prefer_reversed());
+        assert_eq!(renamed.lot_size(), Some("100"));
+        assert!(matches!(
+            swap.with_symbol(Symbol::spot(&Asset::BTC, &Asset::USD)),
+            Err(ParseSymbolError::InvalidSpotFormat)
+        ));
+        let spot = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        assert_eq!(
+            spot.with_symbol(Symbol::spot(&Asset::BTC, &Asset::USDT))
+                .unwrap(),
+            spot
+        );
+    }
+
+    #[test]
+    fn reversed_constructors() {
+        let spot = Instrument::reversed_spot(&Asset::USD, &Asset::BTC);
+        assert!(spot.is_prefer_reversed());
+        assert!(!Instrument::spot(&Asset::USD, &Asset::BTC).is_prefer_reversed());
+        let swap =
+            Instrument::reversed_derivative("SWAP", "BTC-USD-SWAP", &Asset::USD, &Asset::BTC)
+                .unwrap();
+        assert!(swap.is_prefer_reversed());
+        assert_eq!(swap.as_symbol().to_string(), "SWAP:BTC-USD-SWAP");
+        assert!(
+            Instrument::reversed_derivative("SWAP", "BTC-USD-SWAP", &Asset::BTC, &Asset::BTC)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn to_spot() {
+        let swap = Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::BTC, &Asset::USDT)
+            .unwrap()
+            .with_lot_size("0.01")
+            .unwrap();
+        let spot = swap.to_spot();
+        assert_eq!(spot.as_symbol().to_string(), "BTC-USDT");
+        assert_eq!(spot.lot_size(), None);
+        let inverse =
+            Instrument::reversed_derivative("SWAP", "BTC-USD-SWAP", &Asset::USD, &Asset::BTC)
+                .unwrap();
+        let spot = inverse.to_spot();
+        assert_eq!(spot.as_symbol().to_string(), "BTC-USD");
+        assert_eq!((spot.base(), spot.quote()), (&Asset::BTC, &Asset::USD));
+        assert!(!spot.is_prefer_reversed());
+        let spot = Instrument::spot(&Asset::ETH, &Asset::BTC);
+        let same = spot.to_spot();
+        assert_eq!(same, spot);
+        assert_eq!((same.base(), same.quote()), (spot.base(), spot.quote()));
+    }
+
+    #[test]
+    fn same_market() {
+        let btc_usdt = Symbol::spot(&Asset::BTC, &Asset::USDT);
+        let usdt_btc = Symbol::spot(&Asset::USDT, &Asset::BTC);
+        assert!(btc_usdt.is_same_market(&usdt_btc));
+        assert!(btc_usdt.is_same_market(&btc_usdt));
+        assert!(!btc_usdt.is_same_market(&Symbol::spot(&Asset::ETH, &Asset::USDT)));
+        let swap = Symbol::derivative("SWAP", "BTC-USDT-SWAP").unwrap();
+        assert!(swap.is_same_market(&swap));
+        assert!(!swap.is_same_market(&btc_usdt));
+        assert!(!swap.is_same_market(&Symbol::derivative("FUTURES", "BTC-USDT-SWAP").unwrap()));
+        let spot = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        assert!(spot.is_same_market(&spot.to_reversed_spot().unwrap()));
+    }
+
+    #[test]
+    fn cache() {
+        let mut cache = InstrumentCache::new();
+        assert!(cache.is_empty());
+        let swap = cache
+            .get_or_try_insert("SWAP:BTC-USDT-SWAP", &Asset::BTC, &Asset::USDT)
+            .unwrap()
+            .clone();
+        assert_eq!(swap.as_symbol().to_string(), "SWAP:BTC-USDT-SWAP");
+        let cached = cache
+            .get_or_try_insert("SWAP:BTC-USDT-SWAP", &Asset::BTC, &Asset::USDT)
+            .unwrap();
+        assert_eq!(cached, &swap);
+        assert!(cache
+            .get_or_try_insert("ETH-USDT", &Asset::BTC, &Asset::USDT)
+            .is_err());
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get("SWAP:BTC-USDT-SWAP"), Some(&swap));
+        cache.clear();
+        assert!(cache.get("SWAP:BTC-USDT-SWAP").is_none());
+    }
+
+    #[test]
+    fn position_from_notional() {
+        use rust_decimal_macros::dec;
+
+        let swap = Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::BTC, &Asset::USDT).unwrap();
+        let p = swap
+            .position_from_notional(dec!(16000), dec!(8000))
+            .unwrap();
+        assert_eq!(p.size(), dec!(0.5));
+        assert_eq!(p.notional_value(), dec!(8000));
+        let inverse = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::USD, &Asset::BTC)
+            .unwrap()
+            .prefer_reversed(true);
+        let p = inverse
+            .position_from_notional(dec!(16000), dec!(1))
+            .unwrap();
+        assert_eq!(p.price(), Some(dec!(16000)));
+        assert_eq!(p.size(), dec!(-16000));
+        assert_eq!(p.notional_value(), dec!(1));
+        assert!(inverse.position_from_notional(dec!(0), dec!(1)).is_none());
+    }
+
+    #[test]
+    fn same_base_quote() {
+        assert!(matches!(
+            Instrument::try_new("BTC-BTC", &Asset::BTC, &Asset::BTC),
+            Err(ParseSymbolError::SameBaseQuote)
+        ));
+        assert!(matches!(
+            Instrument::try_new("SWAP:BTC-BTC-SWAP", &Asset::BTC, &Asset::BTC),
+            Err(ParseSymbolError::SameBaseQuote)
+        ));
+        assert!(matches!(
+            Instrument::derivative("SWAP", "BTC-BTC-SWAP", &Asset::BTC, &Asset::BTC),
+            Err(ParseSymbolError::SameBaseQuote)
+        ));
+        assert!(matches!(
+            Instrument::try_spot(&Asset::BTC, &Asset::BTC),
+            Err(ParseSymbolError::SameBaseQuote)
+        ));
+        assert!(Instrument::try_spot(&Asset::BTC, &Asset::USDT).is_ok());
+    }
+
+    #[test]
+    fn round_size() {
+        use rust_decimal_macros::dec;
+
+        let swap = Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::BTC, &Asset::USDT)
+            .unwrap()
+            .with_lot_size("0.001")
+            .unwrap();
+        assert_eq!(swap.lot_size(), Some("0.001"));
+        assert_eq!(swap.round_size(&dec!(1.23456)), Some(dec!(1.234)));
+        assert_eq!(swap.round_size(&dec!(-1.23456)), Some(dec!(-1.234)));
+        assert_eq!(swap.round_size(&dec!(0.0009)), Some(dec!(0)));
+        assert_eq!(swap.round_size(&1_i64), None);
+        let swap = swap.with_min_size("0.01").unwrap();
+        assert_eq!(swap.min_size(), Some("0.01"));
+        assert_eq!(swap.round_size(&dec!(0.0099)), Some(dec!(0)));
+        assert_eq!(swap.round_size(&dec!(-0.0123)), Some(dec!(-0.012)));
+        let spot = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        assert_eq!(spot.round_size(&dec!(1.23456)), Some(dec!(1.23456)));
+        assert!(spot.to_reversed_spot().unwrap().lot_size().is_none());
+        for invalid in ["", "abc", "-1", "1.", ".5", "1e-3", "0.0.1"] {
+            assert_eq!(
+                spot.clone().with_lot_size(invalid).unwrap_err(),
+                ParseLotSizeError::Invalid
+            );
+        }
+        for zero in ["0", "0.000"] {
+            assert_eq!(
+                spot.clone().with_lot_size(zero).unwrap_err(),
+                ParseLotSizeError::Zero
+            );
+        }
+        let half = spot.clone().with_lot_size("0.5").unwrap();
+        assert_eq!(half.round_size(&1.75_f64), Some(1.5));
+        assert!(spot.with_min_size("0").is_err());
+    }
+
+    #[test]
+    fn display_pair() {
+        let swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::USD, &Asset::BTC)
+            .unwrap()
+            .prefer_reversed(true);
+        assert_eq!(swap.to_string(), "SWAP:BTC-USD-SWAP");
+        assert_eq!(format!("{swap:#}"), "SWAP:BTC-USD-SWAP*");
+        assert_eq!(format!("{:#}", swap.to_spot()), "BTC-USD");
+        assert_eq!(swap.display_pair(), "USD/BTC");
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn symbol_serde() -> anyhow::Result<()> {
-        use alloc::{vec, vec::Vec};
+        use alloc::vec::Vec;
 
         let value = serde_json::json!(["futures:BTC-USDT-210101", "USDT-BTC"]);
         let assets: Vec<Symbol> = serde_json::from_value(value)?;
@@ -51,7 +51,7 @@ impl<Rep: Representation, T: PositionNum> Clone for Position<Rep, T> {
     fn clone(&self) -> Self {
         Self {
             naive: self.naive.clone(),
-            _rep: PhantomData::default(),
+            _rep: PhantomData,
         }
     }
 }
@@ -62,7 +62,7 @@ impl<Rep: Representation, T: PositionNum> Default for Position<Rep, T> {
     fn default() -> Self {
         Self {
             naive: NaivePosition::default(),
-            _rep: PhantomData::default(),
+            _rep: PhantomData,
         }
     }
 }
@@ -72,20 +72,16 @@ impl<Rep: Representation, T: PositionNum> Position<Rep, T> {
     pub(crate) fn with_naive<H: IntoNaivePosition<T>>(naive: H) -> Self {
         Self {
             naive: naive.into_naive(),
-            _rep: PhantomData::default(),
+            _rep: PhantomData,
         }
     }
 
     /// Create a new [`Position`].
     pub fn new(price: T, size: T, value: T) -> Option<Self> {
         if Rep::is_reversed() {
-            if price.is_zero() {
-                None
-            } else {
-                let price = T::one() / price;
-                let size = size.neg();
-                Some(Self::with_naive(NaivePosition::new(price, size, value)))
-            }
+            let price = price.checked_reciprocal()?;
+            let size = size.neg();
+            Some(Self::with_naive(NaivePosition::new(price, size, value)))
         } else {
             Some(Self::with_naive(NaivePosition::new(price, size, value)))
         }
@@ -132,11 +128,7 @@ impl<Rep: Representation, T: PositionNum> Position<Rep, T> {
     /// Return `None` if naive price is zero when `Rep` is reversed.
     pub fn price(&self) -> Option<T> {
         if Rep::is_reversed() {
-            if self.naive.price.is_zero() {
-                None
-            } else {
-                Some(T::one() / self.naive.price.clone())
-            }
+            self.naive.price.checked_reciprocal()
         } else {
             Some(self.naive.price.clone())
         }
@@ -8,7 +8,12 @@ extern crate alloc;
 
 use num_traits::{NumAssignRef, Signed};
 
-pub use naive_position::{IntoNaivePosition, NaivePosition, Reversed, ToNaivePosition};
+pub use naive_position::{
+    IntoNaivePosition, MergeReport, NaivePosition, Reversed, ToNaivePosition,
+};
+
+#[cfg(feature = "serde")]
+pub use naive_position::Tagged;
 
 /// Naive position without price representation.
 pub mod naive_position;
@@ -25,10 +30,18 @@ pub mod asset;
 #[cfg(feature = "alloc")]
 pub mod instrument;
 
+/// Symbol parsers.
+#[cfg(feature = "alloc")]
+pub mod parser;
+
 /// Position Tree.
 #[cfg(feature = "alloc")]
 pub mod tree;
 
+/// Price providers.
+#[cfg(feature = "alloc")]
+pub mod price;
+
 /// Legacy.
 #[deprecated(since = "0.2.0")]
 pub mod legacy;
@@ -37,27 +50,54 @@ pub mod legacy;
 #[cfg(feature = "alloc")]
 pub mod prelude {
     pub use crate::asset::{Asset, ParseAssetError};
-    pub use crate::instrument::{Instrument, ParseSymbolError, Symbol};
-    pub use crate::naive_position::{IntoNaivePosition, NaivePosition, Reversed, ToNaivePosition};
-    pub use crate::position::{Expr, Position, Positions};
+    pub use crate::instrument::{
+        Instrument, InstrumentCache, ParseLotSizeError, ParseSymbolError, Symbol,
+    };
+    pub use crate::naive_position::{
+        IntoNaivePosition, MergeReport, NaivePosition, Reversed, ToNaivePosition,
+    };
+    pub use crate::parser::SymbolParser;
+    #[cfg(feature = "std")]
+    pub use crate::position::StaleError;
+    pub use crate::position::{
+        annualize, cross_rate, diff_snapshots, CompiledExpr, ConvertError, Discrepancy,
+        EquityTracker, Expr, ImbalanceError, IntoPositionError, LedgerEntry, LedgerReason,
+        MergeError, OwnedExpr, ParsePositionsError, Position, Positions, SnapshotDiff,
+        SymbolConflict,
+    };
+    pub use crate::price::PriceProvider;
+    pub use crate::tree::MissingPrice;
     pub use crate::PositionNum;
 
     #[cfg(not(feature = "std"))]
-    pub use hashbrown::HashMap;
+    pub use hashbrown::{HashMap, HashSet};
 
     #[cfg(feature = "std")]
-    pub use std::collections::HashMap;
+    pub use std::collections::{HashMap, HashSet};
 
     pub use smol_str::SmolStr as Str;
 }
 
 #[cfg(feature = "alloc")]
 pub use prelude::{
-    Asset, Expr, HashMap, Instrument, ParseAssetError, ParseSymbolError, Position, Positions,
-    Symbol,
+    annualize, cross_rate, diff_snapshots, Asset, CompiledExpr, Expr, HashMap, Instrument,
+    ParseAssetError, ParseSymbolError, Position, Positions, SnapshotDiff, Symbol,
 };
 
 /// Num trait that is required by position.
-pub trait PositionNum: NumAssignRef + Signed + Clone + PartialOrd {}
+pub trait PositionNum: NumAssignRef + Signed + Clone + PartialOrd {
+    /// Get the reciprocal (`1 / self`), which is used to convert
+    /// a price to the reversed-form.
+    /// Return [`None`] if `self` is zero.
+    fn checked_reciprocal(&self) -> Option<Self> {
+        if self.is_zero() {
+            None
+        } else {
+            let mut v = Self::one();
+            v /= self;
+            Some(v)
+        }
+    }
+}
 
 impl<T: NumAssignRef + Signed + Clone + PartialOrd> PositionNum for T {}
@@ -11,7 +11,7 @@ impl<T: PositionNum> NaivePosition<T> {
         #[allow(deprecated)]
         Position {
             naive: self,
-            _rep: core::marker::PhantomData::default(),
+            _rep: core::marker::PhantomData,
         }
     }
 }
@@ -1,6 +1,9 @@
 use super::PositionNum;
-use core::ops::{Add, AddAssign, Neg, Sub, SubAssign};
-use num_traits::Zero;
+use core::{
+    fmt,
+    ops::{Add, AddAssign, Neg, Sub, SubAssign},
+};
+use num_traits::{SaturatingAdd, SaturatingMul, SaturatingSub, Zero};
 
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
@@ -20,6 +23,17 @@ pub struct NaivePosition<T> {
     pub value: T,
 }
 
+/// The report of merging a position by [`NaivePosition::add_reporting`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MergeReport<T> {
+    /// The value realized by closing the position.
+    pub realized: T,
+    /// The size closed, which is always non-negative.
+    pub closed_size: T,
+    /// Whether the direction of the position is flipped.
+    pub flipped: bool,
+}
+
 impl<T: PositionNum> Default for NaivePosition<T> {
     fn default() -> Self {
         Self {
@@ -30,12 +44,83 @@ impl<T: PositionNum> Default for NaivePosition<T> {
     }
 }
 
+#[cfg(feature = "arbitrary")]
+impl<'a, T: arbitrary::Arbitrary<'a>> arbitrary::Arbitrary<'a> for NaivePosition<T> {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self {
+            price: T::arbitrary(u)?,
+            size: T::arbitrary(u)?,
+            value: T::arbitrary(u)?,
+        })
+    }
+}
+
 impl<T: PositionNum> NaivePosition<T> {
     /// Create a new [`NaivePosition`].
     pub fn new(price: T, size: T, value: T) -> Self {
         Self { price, size, value }
     }
 
+    /// Create a position at the given `price` with the size of the given `notional` value,
+    /// i.e. `size = notional / price`, and zero value.
+    /// Return [`None`] if `price` is zero.
+    pub fn from_notional(price: T, notional: T) -> Option<Self> {
+        if price.is_zero() {
+            return None;
+        }
+        let mut size = notional;
+        size /= &price;
+        Some(Self {
+            price,
+            size,
+            value: T::zero(),
+        })
+    }
+
+    /// Set the `price` of the position.
+    pub fn with_price(mut self, price: T) -> Self {
+        self.price = price;
+        self
+    }
+
+    /// Set the `size` of the position.
+    pub fn with_size(mut self, size: T) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// Set the `value` of the position.
+    pub fn with_value(mut self, value: T) -> Self {
+        self.value = value;
+        self
+    }
+
+    /// Convert between the reversed-form and the true form,
+    /// i.e. `(1 / price, -size, value)`, which is its own inverse.
+    ///
+    /// Return `None` if `price` is zero.
+    pub fn to_reversed(&self) -> Option<Self> {
+        Some(Self {
+            price: self.price.checked_reciprocal()?,
+            size: self.size.clone().neg(),
+            value: self.value.clone(),
+        })
+    }
+
+    /// Convert between the reversed-form and the true form in place,
+    /// see [`NaivePosition::to_reversed`].
+    ///
+    /// Return `false` and leave the position unchanged if `price` is zero.
+    pub fn reverse_in_place(&mut self) -> bool {
+        match self.to_reversed() {
+            Some(p) => {
+                *self = p;
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Return a new position that consumes its `value`. (Equivalence I).
     ///
     /// Return `None` if `size` is zero.
@@ -73,6 +158,7 @@ impl<T: PositionNum> NaivePosition<T> {
         let mut value = price.clone();
         value -= &self.price;
         value *= &self.size;
+        value += &self.value;
         Self {
             price,
             size: self.size.clone(),
@@ -84,12 +170,87 @@ impl<T: PositionNum> NaivePosition<T> {
     /// but keep equivalent to the original.
     /// (Equivalence II)
     pub fn convert(&mut self, price: T) {
-        self.value = price.clone();
-        self.value -= &self.price;
-        self.value *= &self.size;
+        let mut delta = price.clone();
+        delta -= &self.price;
+        delta *= &self.size;
+        self.value += delta;
         self.price = price;
     }
 
+    /// Limit `|value|` to `max_abs`, keeping its sign,
+    /// and return the clipped excess (with the same sign as the original `value`),
+    /// so that `value` before is equal to `value` after plus the excess.
+    /// # Panic
+    /// Panic if `max_abs` is negative.
+    pub fn clamp_value(&mut self, max_abs: &T) -> T {
+        assert!(!max_abs.is_negative(), "`max_abs` cannot be negative");
+        if self.value.abs() <= *max_abs {
+            return T::zero();
+        }
+        let clamped = if self.value.is_negative() {
+            max_abs.clone().neg()
+        } else {
+            max_abs.clone()
+        };
+        let excess = self.value.clone() - clamped.clone();
+        self.value = clamped;
+        excess
+    }
+
+    /// Split the position into sub-positions of `|size| = step` at the same price,
+    /// with the last one holding the remainder, e.g. for distributing a fill
+    /// across the levels of a grid.
+    ///
+    /// The `value` is put in the first sub-position, so merging the sub-positions
+    /// gives back the original. A position of zero size is returned as is.
+    /// Return `None` if `step` is not positive.
+    #[cfg(feature = "alloc")]
+    pub fn partition_by_step(&self, step: &T) -> Option<alloc::vec::Vec<Self>> {
+        if *step <= T::zero() {
+            return None;
+        }
+        let mut parts = alloc::vec::Vec::new();
+        let mut remaining = self.size.abs();
+        let mut value = self.value.clone();
+        while remaining > T::zero() || parts.is_empty() {
+            let mut size = if remaining > *step {
+                step.clone()
+            } else {
+                remaining.clone()
+            };
+            remaining -= &size;
+            if self.size.is_negative() {
+                size = size.neg();
+            }
+            parts.push(Self {
+                price: self.price.clone(),
+                size,
+                value: core::mem::replace(&mut value, T::zero()),
+            });
+        }
+        Some(parts)
+    }
+
+    /// Check the obviously broken states, as a development aid for the tests
+    /// to call after each operation:
+    /// - `price`, `size` and `value` must be comparable with themselves (no NaN).
+    /// - `price` must be positive if `size` is not zero, which is also required
+    ///   by the reversed-form (the reciprocal of the price).
+    pub fn debug_invariants(&self) -> Result<(), &'static str> {
+        let is_nan = |x: &T| x.partial_cmp(x).is_none();
+        if is_nan(&self.price) {
+            Err("`price` is NaN")
+        } else if is_nan(&self.size) {
+            Err("`size` is NaN")
+        } else if is_nan(&self.value) {
+            Err("`value` is NaN")
+        } else if !self.size.is_zero() && self.price <= T::zero() {
+            Err("`price` of a non-zero position is not positive")
+        } else {
+            Ok(())
+        }
+    }
+
     /// Take the `value` and keep the `price` and `size` unchanged.
     ///
     /// After the operation, the new position is no longer
@@ -99,6 +260,131 @@ impl<T: PositionNum> NaivePosition<T> {
         core::mem::swap(&mut self.value, &mut value);
         value
     }
+
+    /// Add the given position and report the closed size, the realized value and
+    /// whether the direction is flipped, which happens when the added position
+    /// is in the opposite direction and larger than this position.
+    ///
+    /// The `value` carried by `rhs` is added but not counted as realized.
+    pub fn add_reporting(&mut self, rhs: impl IntoNaivePosition<T>) -> MergeReport<T> {
+        let rhs = rhs.into_naive();
+        let size = self.size.clone();
+        let mut realized = self.value.clone().neg() - rhs.value.clone();
+        let opposite = (size.is_positive() && rhs.size.is_negative())
+            || (size.is_negative() && rhs.size.is_positive());
+        let flipped = opposite && rhs.size.abs() > size.abs();
+        let closed_size = if !opposite {
+            T::zero()
+        } else if flipped {
+            size.abs()
+        } else {
+            rhs.size.abs()
+        };
+        *self += rhs;
+        realized += &self.value;
+        MergeReport {
+            realized,
+            closed_size,
+            flipped,
+        }
+    }
+
+    /// Compare with the given position as [`PartialEq`] does, i.e. modulo the
+    /// equivalence of consuming the value, but allow each of `price`, `size`
+    /// and `value` to differ by at most `epsilon`, e.g. for the residues
+    /// of decimal arithmetic.
+    pub fn approx_eq(&self, other: &Self, epsilon: &T) -> bool {
+        let close = |lhs: &T, rhs: &T| (lhs.clone() - rhs.clone()).abs() <= *epsilon;
+        if !close(&self.size, &other.size) {
+            false
+        } else if close(&self.price, &other.price) && close(&self.value, &other.value) {
+            true
+        } else if self.size.is_zero() && other.size.is_zero() {
+            close(&self.value, &other.value)
+        } else {
+            match (self.consumed(), other.consumed()) {
+                (Some(lhs), Some(rhs)) => close(&lhs.price, &rhs.price),
+                _ => false,
+            }
+        }
+    }
+
+    /// Get the average of the prices of the two positions weighted by the absolute sizes.
+    ///
+    /// Return `None` if both sizes are zero.
+    pub fn weighted_mid(&self, other: &Self) -> Option<T> {
+        let lhs = self.size.abs();
+        let rhs = other.size.abs();
+        let total = lhs.clone() + rhs.clone();
+        if total.is_zero() {
+            return None;
+        }
+        let mut mid = self.price.clone() * lhs;
+        mid += other.price.clone() * rhs;
+        mid /= total;
+        Some(mid)
+    }
+
+    /// Add the given position as `+` does, but clamp every intermediate result
+    /// at the bounds of `T` instead of overflowing, e.g. for integer-backed positions
+    /// shown in the UI that must never panic on bad data.
+    /// # Warning
+    /// The result is lossy once clamped, so it is only for presentation, not accounting.
+    pub fn saturating_add(self, rhs: impl IntoNaivePosition<T>) -> Self
+    where
+        T: SaturatingAdd + SaturatingMul + SaturatingSub,
+    {
+        let magnitude = |x: &T| {
+            if x.is_negative() {
+                T::zero().saturating_sub(x)
+            } else {
+                x.clone()
+            }
+        };
+        let (mut lhs, mut rhs) = (self, rhs.into_naive());
+        if magnitude(&lhs.size) <= magnitude(&rhs.size) {
+            core::mem::swap(&mut lhs, &mut rhs);
+        }
+        if rhs.size.is_zero() {
+            lhs.value = lhs.value.saturating_add(&rhs.value);
+        } else if (lhs.size.is_positive() && rhs.size.is_positive())
+            || (lhs.size.is_negative() && rhs.size.is_negative())
+        {
+            let total = lhs.size.saturating_add(&rhs.size);
+            let notional = lhs
+                .price
+                .saturating_mul(&lhs.size)
+                .saturating_add(&rhs.price.saturating_mul(&rhs.size));
+            lhs.price = notional / total.clone();
+            lhs.size = total;
+            lhs.value = lhs.value.saturating_add(&rhs.value);
+        } else {
+            lhs.size = lhs.size.saturating_add(&rhs.size);
+            let pnl = rhs
+                .price
+                .saturating_sub(&lhs.price)
+                .saturating_mul(&rhs.size);
+            lhs.value = lhs.value.saturating_add(&rhs.value).saturating_sub(&pnl);
+        }
+        if lhs.size.is_zero() {
+            lhs.price = T::one();
+        }
+        lhs
+    }
+
+    /// Convert the numeric type of the position with the given function,
+    /// which is applied to `price`, `size` and `value`.
+    pub fn map<U, F>(self, f: F) -> NaivePosition<U>
+    where
+        U: PositionNum,
+        F: Fn(T) -> U,
+    {
+        NaivePosition {
+            price: f(self.price),
+            size: f(self.size),
+            value: f(self.value),
+        }
+    }
 }
 
 impl<T: PositionNum, H> PartialEq<H> for NaivePosition<T>
@@ -126,13 +412,31 @@ where
 
 impl<T: PositionNum> Eq for NaivePosition<T> {}
 
+/// Display the raw form `(price, size) + value`,
+/// without applying any reversed logic.
+impl<T> fmt::Display for NaivePosition<T>
+where
+    T: PositionNum + fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.value.is_negative() { "-" } else { "+" };
+        write!(
+            f,
+            "({}, {}) {sign} {}",
+            self.price,
+            self.size,
+            self.value.abs()
+        )
+    }
+}
+
 impl<T: PositionNum> Zero for NaivePosition<T> {
     fn zero() -> Self {
         Self::default()
     }
 
     fn is_zero(&self) -> bool {
-        self.size.is_one() && self.value.is_zero()
+        self.size.is_zero() && self.value.is_zero()
     }
 }
 
@@ -199,15 +503,124 @@ where
     /// # Panic
     /// Panic if the `price` is zero.
     fn into_naive(self) -> NaivePosition<T> {
-        let NaivePosition { price, size, value } = self.0.into_naive();
-        if price.is_zero() {
-            panic!("zero price cannot be convert into reversed form");
+        self.0
+            .into_naive()
+            .to_reversed()
+            .expect("zero price cannot be convert into reversed form")
+    }
+}
+
+/// A [`NaivePosition`] tagged with whether it is the true form of a reversed position,
+/// see [`NaivePosition::tagged`].
+///
+/// It is serialized in the display form, i.e. the price and size of a reversed position
+/// are converted to the reversed-form and a `reversed: true` flag is added,
+/// which is far more readable than the reciprocals. The true form is reconstructed
+/// on deserialization. Note that the conversion may lose precision,
+/// so use [`NaivePosition`] directly for lossless round-trips.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, Copy)]
+pub struct Tagged<T> {
+    position: NaivePosition<T>,
+    reversed: bool,
+}
+
+#[cfg(feature = "serde")]
+impl<T> Tagged<T> {
+    /// Is the position the true form of a reversed position.
+    pub fn is_reversed(&self) -> bool {
+        self.reversed
+    }
+
+    /// Get the position in the true form.
+    pub fn into_inner(self) -> NaivePosition<T> {
+        self.position
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: PositionNum> NaivePosition<T> {
+    /// Tag the position with whether it is the true form of a reversed position,
+    /// for serializing in the display form.
+    pub fn tagged(self, reversed: bool) -> Tagged<T> {
+        Tagged {
+            position: self,
+            reversed,
         }
-        NaivePosition {
-            price: T::one() / price,
-            size: -size,
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: PositionNum> IntoNaivePosition<T> for Tagged<T> {
+    fn into_naive(self) -> NaivePosition<T> {
+        self.position
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct TaggedRepr<T> {
+    price: T,
+    size: T,
+    value: T,
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    reversed: bool,
+}
+
+#[cfg(feature = "serde")]
+impl<T> Serialize for Tagged<T>
+where
+    T: PositionNum + Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let NaivePosition { price, size, value } = self.position.clone();
+        let (price, size) = if self.reversed {
+            let price = price.checked_reciprocal().ok_or_else(|| {
+                serde::ser::Error::custom("zero price cannot be convert into reversed form")
+            })?;
+            (price, -size)
+        } else {
+            (price, size)
+        };
+        TaggedRepr {
+            price,
+            size,
             value,
+            reversed: self.reversed,
         }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T> Deserialize<'de> for Tagged<T>
+where
+    T: PositionNum + Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let TaggedRepr {
+            price,
+            size,
+            value,
+            reversed,
+        } = TaggedRepr::<T>::deserialize(deserializer)?;
+        let position = if reversed {
+            if price.is_zero() {
+                return Err(serde::de::Error::custom(
+                    "zero price cannot be convert into reversed form",
+                ));
+            }
+            Reversed((price, size, value)).into_naive()
+        } else {
+            NaivePosition { price, size, value }
+        };
+        Ok(Self { position, reversed })
     }
 }
 
@@ -251,6 +664,11 @@ where
             rhs.price -= &self.price;
             self.value += rhs.value + rhs.price * rhs.size.neg();
         }
+        // The price of a closed position is meaningless and would depend on the order
+        // of the operands, so it is reset to the price of `NaivePosition::default`.
+        if self.size.is_zero() {
+            self.price = T::one();
+        }
     }
 }
 
@@ -331,4 +749,304 @@ mod test {
         assert_eq!(h.price, -1);
         assert_eq!(h.size, 2);
     }
+
+    #[test]
+    fn convert_keeps_value() {
+        let mut h = NaivePosition::new(5, 2, 3);
+        assert_eq!(h.converted(7), (7, 2, 7));
+        assert_eq!(h.converted(7), h);
+        h.convert(7);
+        assert_eq!((h.price, h.size, h.value), (7, 2, 7));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn display() {
+        use alloc::string::ToString;
+
+        let h = (2.0, -1.5, 3.0).into_naive();
+        assert_eq!(h.to_string(), "(2, -1.5) + 3");
+        let h = (0.5, 4.0, -1.0).into_naive();
+        assert_eq!(h.to_string(), "(0.5, 4) - 1");
+    }
+
+    #[test]
+    fn to_reversed() {
+        let h = (2.0, 1.5, 3.0).into_naive();
+        let r = h.to_reversed().unwrap();
+        assert_eq!((r.price, r.size, r.value), (0.5, -1.5, 3.0));
+        assert_eq!(r.to_reversed().unwrap(), h);
+        assert_eq!(Reversed((2.0, 1.5, 3.0)).into_naive(), r);
+        let mut z = (0.0, 1.0, 1.0).into_naive();
+        assert!(z.to_reversed().is_none());
+        assert!(!z.reverse_in_place());
+        assert_eq!((z.price, z.size, z.value), (0.0, 1.0, 1.0));
+        let mut h = h;
+        assert!(h.reverse_in_place());
+        assert_eq!((h.price, h.size, h.value), (0.5, -1.5, 3.0));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn partition_by_step() {
+        let h = (10, 7, 3).into_naive();
+        let parts = h.partition_by_step(&3).unwrap();
+        assert_eq!(
+            parts
+                .iter()
+                .map(|p| (p.price, p.size, p.value))
+                .collect::<alloc::vec::Vec<_>>(),
+            [(10, 3, 3), (10, 3, 0), (10, 1, 0)]
+        );
+        assert_eq!(
+            parts
+                .into_iter()
+                .fold(NaivePosition::default(), |acc, p| acc + p),
+            h
+        );
+        let parts = (10, -6, 0).into_naive().partition_by_step(&3).unwrap();
+        assert_eq!(
+            parts.iter().map(|p| p.size).collect::<alloc::vec::Vec<_>>(),
+            [-3, -3]
+        );
+        let parts = (10, 0, 5).into_naive().partition_by_step(&3).unwrap();
+        assert_eq!(parts, [(10, 0, 5)]);
+        assert!(h.partition_by_step(&0).is_none());
+        assert!(h.partition_by_step(&-1).is_none());
+        assert!((1.0, 2.0, 0.0)
+            .into_naive()
+            .partition_by_step(&0.0)
+            .is_none());
+    }
+
+    #[test]
+    fn debug_invariants() {
+        let mut h = (2.0, 1.5, 3.0).into_naive();
+        assert!(h.debug_invariants().is_ok());
+        h += Reversed((4.0, 2.0));
+        assert!(h.debug_invariants().is_ok());
+        h += (0.25, 2.0, 0.0);
+        assert!(h.debug_invariants().is_ok());
+        assert!((0.0, 1.0, 0.0).into_naive().debug_invariants().is_err());
+        assert!((-1.0, 1.0, 0.0).into_naive().debug_invariants().is_err());
+        assert!((0.0, 0.0, 1.0).into_naive().debug_invariants().is_ok());
+        assert!((1.0, f64::NAN, 0.0)
+            .into_naive()
+            .debug_invariants()
+            .is_err());
+        assert!((1.0, 1.0, f64::NAN)
+            .into_naive()
+            .debug_invariants()
+            .is_err());
+    }
+
+    #[test]
+    fn from_notional() {
+        let h = NaivePosition::from_notional(16000.0, -8000.0).unwrap();
+        assert_eq!((h.price, h.size, h.value), (16000.0, -0.5, 0.0));
+        assert!(NaivePosition::from_notional(0.0, 1.0).is_none());
+    }
+
+    #[test]
+    fn clamp_value() {
+        let mut h = NaivePosition::new(5, 2, 7);
+        assert_eq!(h.clamp_value(&10), 0);
+        assert_eq!(h.value, 7);
+        assert_eq!(h.clamp_value(&3), 4);
+        assert_eq!(h.value, 3);
+        let mut h = NaivePosition::new(5, 2, -7);
+        assert_eq!(h.clamp_value(&3), -4);
+        assert_eq!(h.value, -3);
+        assert_eq!(h.clamp_value(&0), -3);
+        assert_eq!((h.price, h.size, h.value), (5, 2, 0));
+    }
+
+    #[test]
+    fn zero_crossing() {
+        let lhs = NaivePosition::new(5, 2, 0);
+        let rhs = NaivePosition::new(7, -2, 1);
+        let a = lhs + rhs;
+        let b = rhs + lhs;
+        assert_eq!((a.price, a.size, a.value), (1, 0, 5));
+        assert_eq!((b.price, b.size, b.value), (1, 0, 5));
+        assert!(NaivePosition::<i64>::default().is_zero());
+        assert!(!(NaivePosition::new(1, 1, 0)).is_zero());
+    }
+
+    #[cfg(feature = "std")]
+    mod properties {
+        use super::*;
+        use fraction::BigFraction;
+        use proptest::prelude::*;
+
+        fn fill() -> impl Strategy<Value = NaivePosition<BigFraction>> {
+            (1u64..100, -5i64..=5, -3i64..=3).prop_map(|(price, size, value)| {
+                NaivePosition::new(
+                    BigFraction::from(price),
+                    BigFraction::from(size),
+                    BigFraction::from(value),
+                )
+            })
+        }
+
+        fn value_at(p: &NaivePosition<BigFraction>, price: u64) -> BigFraction {
+            let mut delta = BigFraction::from(price);
+            delta -= &p.price;
+            delta *= &p.size;
+            delta + p.value.clone()
+        }
+
+        fn fold(fills: &[NaivePosition<BigFraction>]) -> NaivePosition<BigFraction> {
+            fills
+                .iter()
+                .cloned()
+                .fold(NaivePosition::default(), |acc, x| acc + x)
+        }
+
+        proptest! {
+            #[test]
+            fn order_independent(
+                (fills, shuffled) in prop::collection::vec(fill(), 0..12)
+                    .prop_flat_map(|fills| (Just(fills.clone()), Just(fills).prop_shuffle()))
+            ) {
+                let lhs = fold(&fills);
+                let rhs = fold(&shuffled);
+                prop_assert_eq!(&lhs.size, &rhs.size);
+                for price in [1, 37, 100] {
+                    prop_assert_eq!(value_at(&lhs, price), value_at(&rhs, price));
+                }
+                prop_assert_eq!(lhs, rhs);
+            }
+
+            #[test]
+            fn associative(a in fill(), b in fill(), c in fill()) {
+                let lhs = (a.clone() + b.clone()) + c.clone();
+                let rhs = a + (b + c);
+                prop_assert_eq!(&lhs.size, &rhs.size);
+                for price in [1, 37, 100] {
+                    prop_assert_eq!(value_at(&lhs, price), value_at(&rhs, price));
+                }
+                if lhs.size.is_zero() {
+                    prop_assert_eq!(&lhs.price, &rhs.price);
+                }
+                prop_assert_eq!(lhs, rhs);
+            }
+        }
+    }
+
+    #[test]
+    fn checked_reciprocal() {
+        assert_eq!(2.0.checked_reciprocal(), Some(0.5));
+        assert_eq!(0.0.checked_reciprocal(), None);
+        assert_eq!(0.checked_reciprocal(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "zero price cannot be convert into reversed form")]
+    fn reversed_zero_price() {
+        let _ = Reversed((0.0, 1.0)).into_naive();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_tagged() -> anyhow::Result<()> {
+        let p = Reversed((16000.0, -1600.0, 0.5)).into_naive();
+        let s = serde_json::to_string(&p.tagged(true))?;
+        assert_eq!(
+            s,
+            r#"{"price":16000.0,"size":-1600.0,"value":0.5,"reversed":true}"#
+        );
+        let tagged: Tagged<f64> = serde_json::from_str(&s)?;
+        assert!(tagged.is_reversed());
+        assert_eq!(tagged.into_inner(), p);
+        let q = NaivePosition::new(2.0, 3.0, 4.0);
+        let s = serde_json::to_string(&q.tagged(false))?;
+        assert_eq!(s, r#"{"price":2.0,"size":3.0,"value":4.0}"#);
+        assert_eq!(serde_json::from_str::<Tagged<f64>>(&s)?.into_naive(), q);
+        assert!(serde_json::from_str::<Tagged<f64>>(
+            r#"{"price":0.0,"size":1.0,"value":0.0,"reversed":true}"#
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn approx_eq() {
+        let h = (2.0, 1.5, 3.0).into_naive();
+        assert!(h.approx_eq(&(2.0 + 1e-12, 1.5, 3.0 - 1e-12).into_naive(), &1e-9));
+        assert!(!h.approx_eq(&(2.1, 1.5, 3.0).into_naive(), &1e-9));
+        assert!(h.approx_eq(&(0.0, 1.5, 0.0).into_naive(), &1e-9));
+        assert!(!h.approx_eq(&(0.0, 1.5 + 1e-6, 0.0).into_naive(), &1e-9));
+        assert!((1.0, 0.0, 2.0)
+            .into_naive()
+            .approx_eq(&(5.0, 0.0, 2.0).into_naive(), &1e-9));
+    }
+
+    #[test]
+    fn saturating_add() {
+        let h = NaivePosition::new(2, 3, 1).saturating_add((4, 1));
+        assert_eq!(h, NaivePosition::new(2, 3, 1) + (4, 1));
+        let h = NaivePosition::new(5, 2, 0).saturating_add((3, -1));
+        assert_eq!(h, NaivePosition::new(5, 2, 0) + (3, -1));
+        let h = NaivePosition::new(i64::MAX, i64::MAX, 0).saturating_add((i64::MAX, i64::MAX));
+        assert_eq!(h.size, i64::MAX);
+        assert_eq!(h.price, 1);
+        let h = NaivePosition::new(i64::MAX, i64::MIN, 0).saturating_add((i64::MIN, 1));
+        assert_eq!(h.size, i64::MIN + 1);
+        assert_eq!(h.value, i64::MAX);
+    }
+
+    #[test]
+    fn weighted_mid() {
+        let lhs = NaivePosition::new(10.0, 1.0, 0.0);
+        let rhs = NaivePosition::new(20.0, -3.0, 0.0);
+        assert_eq!(lhs.weighted_mid(&rhs), Some(17.5));
+        assert_eq!(lhs.weighted_mid(&NaivePosition::default()), Some(10.0));
+        assert_eq!(
+            NaivePosition::<f64>::default().weighted_mid(&NaivePosition::default()),
+            None
+        );
+    }
+
+    #[test]
+    fn add_reporting() {
+        let mut h = NaivePosition::new(5, 2, 1);
+        let report = h.add_reporting((6, 1));
+        assert_eq!(report.closed_size, 0);
+        assert_eq!(report.realized, 0);
+        assert!(!report.flipped);
+        let report = h.add_reporting((7, -1, 3));
+        assert_eq!(report.closed_size, 1);
+        assert!(!report.flipped);
+        let report = h.add_reporting((9, -4));
+        assert_eq!(report.closed_size, 2);
+        assert!(report.flipped);
+        assert_eq!(h.size, -2);
+        assert_eq!(h.price, 9);
+        let mut h = NaivePosition::new(5, 2, 0);
+        let report = h.add_reporting((8, -2));
+        assert_eq!(report.realized, 6);
+        assert_eq!(report.closed_size, 2);
+        assert!(!report.flipped);
+        let mut h = NaivePosition::new(5, 1, 0);
+        let report = h.add_reporting((8, -3));
+        assert_eq!(report.realized, 3);
+        assert!(report.flipped);
+    }
+
+    #[test]
+    fn builder() {
+        let h = NaivePosition::default().with_size(2).with_price(5);
+        assert_eq!(h, (5, 2));
+        assert_eq!(h.with_value(3), (5, 2, 3));
+        assert_eq!(NaivePosition::default().with_value(3), 3);
+    }
+
+    #[test]
+    fn map() {
+        let h = NaivePosition::new(1, 2, 3).map(|x| x as f64 / 2.0);
+        assert_eq!(h.price, 0.5);
+        assert_eq!(h.size, 1.0);
+        assert_eq!(h.value, 1.5);
+    }
 }
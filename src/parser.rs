@@ -0,0 +1,228 @@
+use core::str::FromStr;
+
+use crate::{
+    asset::Asset,
+    instrument::{Instrument, ParseSymbolError, Symbol},
+};
+
+/// Symbol parser, converting the symbols of a venue to [`Symbol`]s and [`Instrument`]s.
+pub trait SymbolParser {
+    /// Parse the given str into a [`Symbol`].
+    fn parse_symbol(&self, s: &str) -> Result<Symbol, ParseSymbolError>;
+
+    /// Parse the given str into an [`Instrument`].
+    ///
+    /// The default implementation only supports spots, and return
+    /// [`ParseSymbolError::MissingAssets`] for the derivatives,
+    /// since their base and quote assets cannot be inferred from the symbols.
+    fn parse_instrument(&self, s: &str) -> Result<Instrument, ParseSymbolError> {
+        let symbol = self.parse_symbol(s)?;
+        let Some((base, quote)) = symbol.as_spot() else {
+            return Err(ParseSymbolError::MissingAssets);
+        };
+        let (base, quote) = (base.clone(), quote.clone());
+        Instrument::try_with_symbol(symbol, &base, &quote)
+    }
+}
+
+/// The default parser, accepting the format of [`Symbol`]'s `FromStr`,
+/// i.e. `BTC-USDT` for spots and `SWAP:BTC-USDT-SWAP` for derivatives.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultParser;
+
+impl SymbolParser for DefaultParser {
+    fn parse_symbol(&self, s: &str) -> Result<Symbol, ParseSymbolError> {
+        Symbol::from_str(s)
+    }
+}
+
+/// Parser for the spots delimited by the given char, e.g. `BTC/USDT` or `BTC_USDT`.
+#[derive(Debug, Clone, Copy)]
+pub struct DelimitedParser {
+    sep: char,
+}
+
+impl DelimitedParser {
+    /// Create a parser with the given delimiter.
+    pub fn new(sep: char) -> Self {
+        Self { sep }
+    }
+}
+
+impl SymbolParser for DelimitedParser {
+    fn parse_symbol(&self, s: &str) -> Result<Symbol, ParseSymbolError> {
+        let (base, quote) = s
+            .split_once(self.sep)
+            .ok_or(ParseSymbolError::InvalidSpotFormat)?;
+        Ok(Symbol::spot(
+            &Asset::from_str(base)?,
+            &Asset::from_str(quote)?,
+        ))
+    }
+}
+
+/// Parser for the spots of Binance, e.g. `BTCUSDT`.
+///
+/// The quote asset is detected by matching the known quote assets as suffix.
+#[cfg(feature = "binance")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BinanceParser;
+
+#[cfg(feature = "binance")]
+impl BinanceParser {
+    /// The known quote assets, longer ones are matched first.
+    pub const QUOTES: [&'static str; 8] =
+        ["FDUSD", "USDT", "BUSD", "USDC", "TUSD", "BTC", "ETH", "BNB"];
+}
+
+#[cfg(feature = "binance")]
+impl SymbolParser for BinanceParser {
+    fn parse_symbol(&self, s: &str) -> Result<Symbol, ParseSymbolError> {
+        let upper = s.to_ascii_uppercase();
+        for quote in Self::QUOTES {
+            if let Some(base) = upper.strip_suffix(quote) {
+                if base.is_empty() {
+                    continue;
+                }
+                return Ok(Symbol::spot(
+                    &Asset::from_str(base)?,
+                    &Asset::from_str(quote)?,
+                ));
+            }
+        }
+        Err(ParseSymbolError::InvalidSpotFormat)
+    }
+}
+
+/// Parser for the instruments of OKX.
+///
+/// - `BTC-USDT` is parsed as a spot.
+/// - `BTC-USDT-SWAP` is parsed as a derivative with prefix [`OkxParser::SWAP`].
+/// - `BTC-USD-221230` is parsed as a derivative with prefix [`OkxParser::FUTURES`].
+///
+/// The derivatives quoted in `USD` are coin-margined, so their base asset is `USD`,
+/// their quote asset is the settlement coin and they are reversed-prefering.
+#[cfg(feature = "okx")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OkxParser;
+
+#[cfg(feature = "okx")]
+impl OkxParser {
+    /// The prefix of perpetual swaps.
+    pub const SWAP: &'static str = "SWAP";
+    /// The prefix of futures.
+    pub const FUTURES: &'static str = "FUTURES";
+
+    fn split(s: &str) -> Result<(Asset, Asset, Option<&'static str>), ParseSymbolError> {
+        let mut parts = s.split(Asset::SEP);
+        let (Some(base), Some(quote)) = (parts.next(), parts.next()) else {
+            return Err(ParseSymbolError::InvalidSpotFormat);
+        };
+        let prefix = match (parts.next(), parts.next()) {
+            (None, _) => None,
+            (Some(suffix), None) if suffix.eq_ignore_ascii_case("SWAP") => Some(Self::SWAP),
+            (Some(suffix), None) if suffix.bytes().all(|b| b.is_ascii_digit()) => {
+                Some(Self::FUTURES)
+            }
+            _ => return Err(ParseSymbolError::InvalidPrefix),
+        };
+        Ok((base.parse()?, quote.parse()?, prefix))
+    }
+}
+
+#[cfg(feature = "okx")]
+impl SymbolParser for OkxParser {
+    fn parse_symbol(&self, s: &str) -> Result<Symbol, ParseSymbolError> {
+        match Self::split(s)? {
+            (base, quote, None) => Ok(Symbol::spot(&base, &quote)),
+            (_, _, Some(prefix)) => Symbol::derivative(prefix, &s.to_ascii_uppercase()),
+        }
+    }
+
+    fn parse_instrument(&self, s: &str) -> Result<Instrument, ParseSymbolError> {
+        match Self::split(s)? {
+            (base, quote, None) => Ok(Instrument::spot(&base, &quote)),
+            (base, quote, Some(prefix)) => {
+                let symbol = s.to_ascii_uppercase();
+                if quote == Asset::USD {
+                    Ok(Instrument::derivative(prefix, &symbol, &quote, &base)?
+                        .prefer_reversed(true))
+                } else {
+                    Instrument::derivative(prefix, &symbol, &base, &quote)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_parser() {
+        let spot = DefaultParser.parse_instrument("btc-usdt").unwrap();
+        assert_eq!(spot, Instrument::spot(&Asset::BTC, &Asset::USDT));
+        assert_eq!(
+            DefaultParser.parse_symbol("SWAP:BTC-USDT-SWAP").unwrap(),
+            Symbol::derivative("SWAP", "BTC-USDT-SWAP").unwrap()
+        );
+        assert!(matches!(
+            DefaultParser.parse_instrument("SWAP:BTC-USDT-SWAP"),
+            Err(ParseSymbolError::MissingAssets)
+        ));
+    }
+
+    #[test]
+    fn delimited_parser() {
+        let spot = Symbol::spot(&Asset::BTC, &Asset::USDT);
+        assert_eq!(
+            DelimitedParser::new('/').parse_symbol("BTC/USDT").unwrap(),
+            spot
+        );
+        assert_eq!(
+            DelimitedParser::new('_').parse_symbol("btc_usdt").unwrap(),
+            spot
+        );
+        assert!(DelimitedParser::new('/').parse_symbol("BTC_USDT").is_err());
+    }
+
+    #[cfg(feature = "binance")]
+    #[test]
+    fn binance_parser() {
+        assert_eq!(
+            BinanceParser.parse_symbol("BTCUSDT").unwrap(),
+            Symbol::spot(&Asset::BTC, &Asset::USDT)
+        );
+        assert_eq!(
+            BinanceParser.parse_symbol("ethbtc").unwrap(),
+            Symbol::spot(&Asset::ETH, &Asset::BTC)
+        );
+        assert!(BinanceParser.parse_symbol("USDT").is_err());
+    }
+
+    #[cfg(feature = "okx")]
+    #[test]
+    fn okx_parser() {
+        assert_eq!(
+            OkxParser.parse_instrument("BTC-USDT").unwrap(),
+            Instrument::spot(&Asset::BTC, &Asset::USDT)
+        );
+        let swap = OkxParser.parse_instrument("BTC-USDT-SWAP").unwrap();
+        assert_eq!(
+            swap.as_symbol(),
+            &Symbol::derivative("SWAP", "BTC-USDT-SWAP").unwrap()
+        );
+        assert_eq!(swap.base(), &Asset::BTC);
+        assert!(!swap.is_prefer_reversed());
+        let futures = OkxParser.parse_instrument("btc-usd-221230").unwrap();
+        assert_eq!(
+            futures.as_symbol(),
+            &Symbol::derivative("FUTURES", "BTC-USD-221230").unwrap()
+        );
+        assert_eq!(futures.base(), &Asset::USD);
+        assert_eq!(futures.quote(), &Asset::BTC);
+        assert!(futures.is_prefer_reversed());
+        assert!(OkxParser.parse_symbol("BTC-USD-221230-16000-C").is_err());
+    }
+}
@@ -1,24 +1,78 @@
 use crate::{
-    instrument::{Instrument, Symbol},
-    tree::PositionTree,
+    asset::ParseAssetError,
+    instrument::{Instrument, ParseSymbolError, Symbol},
+    prelude::{HashSet, Str},
+    price::{Layered, PriceProvider, WithReversedSpots},
+    tree::{MissingPrice, PositionTree},
     Asset, HashMap, IntoNaivePosition, NaivePosition, PositionNum, Reversed,
 };
-use alloc::fmt;
-use core::ops::{Add, AddAssign, Deref, Neg, SubAssign};
+use alloc::{
+    boxed::Box,
+    fmt,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    ops::{Add, AddAssign, Deref, Mul, MulAssign, Neg, SubAssign},
+    str::FromStr,
+};
+use num_traits::{CheckedMul, FromPrimitive};
 
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 /// Position.
+///
+/// The metadata `M` (e.g. an open timestamp or a strategy tag) is attached by the caller
+/// with [`Position::with_meta`], and is ignored by the arithmetic and the comparisons.
+/// When merging, the metadata of the left-hand side is kept.
+/// Zero-sized metadata (e.g. the default `()`) is omitted when serializing.
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-pub struct Position<T> {
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: Serialize, M: Serialize",
+        deserialize = "T: Deserialize<'de>, M: Deserialize<'de> + Default"
+    ))
+)]
+pub struct Position<T, M = ()> {
     instrument: Instrument,
     #[cfg_attr(feature = "serde", serde(flatten))]
     naive: NaivePosition<T>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "is_zero_sized")
+    )]
+    meta: M,
+}
+
+#[cfg(feature = "serde")]
+fn is_zero_sized<M>(_meta: &M) -> bool {
+    core::mem::size_of::<M>() == 0
 }
 
-impl<T> Position<T> {
+impl<T, M> Position<T, M> {
+    /// Attach the given metadata, replacing the current one.
+    pub fn with_meta<N>(self, meta: N) -> Position<T, N> {
+        Position {
+            instrument: self.instrument,
+            naive: self.naive,
+            meta,
+        }
+    }
+
+    /// Get the metadata.
+    pub fn meta(&self) -> &M {
+        &self.meta
+    }
+
+    /// Get the mutable reference of the metadata.
+    pub fn meta_mut(&mut self) -> &mut M {
+        &mut self.meta
+    }
+
     /// Get the instrument.
     pub fn instrument(&self) -> &Instrument {
         &self.instrument
@@ -29,6 +83,28 @@ impl<T> Position<T> {
         &self.naive
     }
 
+    /// Get the mutable reference of the [`NaivePosition`].
+    /// # Warning
+    /// This is an escape hatch for low-level corrections, which bypasses
+    /// the reversed-preference handling: the fields must be in the "true form",
+    /// i.e. the price and size of a reversed-prefering instrument are the
+    /// reciprocal and the negation of what [`Position::price`] and [`Position::size`] return.
+    pub fn as_naive_mut(&mut self) -> &mut NaivePosition<T> {
+        &mut self.naive
+    }
+
+    /// Clone the [`NaivePosition`] of the position without cloning the instrument.
+    ///
+    /// This is what [`ToNaivePosition::to_naive`](crate::ToNaivePosition::to_naive)
+    /// does for `&Position`, but can be called on `Position` directly.
+    #[inline]
+    pub fn to_naive(&self) -> NaivePosition<T>
+    where
+        T: Clone,
+    {
+        self.naive.clone()
+    }
+
     /// Get the value of the position.
     pub fn value(&self) -> &T {
         &self.naive.value
@@ -44,6 +120,103 @@ where
         Self {
             instrument,
             naive: position.into_naive(),
+            meta: (),
+        }
+    }
+
+    /// Create a zero position of the given instrument.
+    pub fn zero(instrument: &Instrument) -> Self {
+        Self::new(instrument.clone(), NaivePosition::default())
+    }
+
+    /// Create a new position from the price and size in the display form,
+    /// i.e. the reversed-form if the `instrument` is reversed-prefering.
+    /// # Panic
+    /// Panic if the `instrument` is reversed-prefering and the `price` is zero.
+    pub fn from_display_parts(instrument: Instrument, price: T, size: T) -> Self {
+        if instrument.is_prefer_reversed() {
+            Self::new(instrument, Reversed((price, size)))
+        } else {
+            Self::new(instrument, (price, size))
+        }
+    }
+
+    /// Convert to a position tree.
+    pub fn as_tree(&self) -> PositionTree<'_, T> {
+        PositionTree {
+            asset: self.instrument.quote(),
+            value: T::zero(),
+            positions: HashMap::from([(&self.instrument, self)]),
+            children: HashMap::default(),
+        }
+    }
+}
+
+impl<T, M> Position<T, M>
+where
+    T: PositionNum,
+{
+    /// Close the `frac` of the current size at the given price,
+    /// and return the realized value. `frac` of one closes the position fully.
+    /// # Warning
+    /// The `price` is treated to be in the reversed-form
+    /// if the `instrument` is reversed-prefering.
+    /// # Panic
+    /// Panic if `frac` is not in `(0, 1]`, or the `price` is in the reversed-form and is zero.
+    pub fn reduce_by_fraction(&mut self, frac: &T, price: &T) -> T {
+        assert!(
+            *frac > T::zero() && *frac <= T::one(),
+            "the fraction must be in (0, 1]"
+        );
+        let price = if self.instrument.is_prefer_reversed() {
+            price
+                .checked_reciprocal()
+                .expect("the price in reversed-form cannot be zero")
+        } else {
+            price.clone()
+        };
+        let mut size = self.naive.size.clone();
+        size *= frac;
+        self.naive.add_reporting((price, -size)).realized
+    }
+
+    /// Fold the `funding` received (negative if paid) into the entry price, i.e.
+    /// subtract `funding / size` from the true-form price, keeping the `value` unchanged,
+    /// so that [`Position::price`] reflects the carry-adjusted cost.
+    /// It is a no-op if the size is zero.
+    ///
+    /// This is [`NaivePosition::consume`] applied to an external amount: adding
+    /// the funding to the value instead (e.g. `p += funding`) gives an equivalent
+    /// position, but keeps the entry price unchanged.
+    pub fn amortize_funding(&mut self, funding: &T) {
+        if self.naive.size.is_zero() {
+            return;
+        }
+        let mut delta = funding.clone();
+        delta /= &self.naive.size;
+        self.naive.price -= delta;
+    }
+
+    /// Return an equivalent position in the normal form, i.e. with the reversed
+    /// preference of its instrument dropped.
+    ///
+    /// The stored position is already in the true form (with the base and quote
+    /// of the instrument as declared), so it is kept unchanged and the values
+    /// are preserved.
+    /// # Warning
+    /// This is a change of representation: for a reversed-preferring instrument,
+    /// [`Position::price`] and [`Position::size`] return the true-form price and size
+    /// afterwards, i.e. the reciprocal of the displayed price and the negated displayed
+    /// size, and the prices passed to [`Position::closed`] must be in the true form too.
+    /// Positions of other instruments are returned unchanged.
+    pub fn to_normalized(&self) -> Position<T, M>
+    where
+        M: Clone,
+    {
+        Position {
+            instrument: self.instrument.clone().prefer_reversed(false),
+            naive: self.naive.clone(),
+            meta: self.meta.clone(),
         }
     }
 
@@ -53,28 +226,106 @@ where
     /// so if you want to close a position of a "reversed instrument",
     /// you should provide the price with "reversed form".
     pub fn closed(&self, price: &T) -> T {
-        let mut p = self.naive.clone();
+        // `value + (price - mark) * (-size)` in the true form,
+        // the same as subtracting the position at the mark.
+        let mut pnl = self.naive.price.clone();
         if self.instrument.is_prefer_reversed() {
-            p -= Reversed((price.clone(), self.size()));
+            pnl -= price
+                .checked_reciprocal()
+                .expect("zero price cannot be convert into reversed form");
+        } else {
+            pnl -= price;
+        }
+        pnl *= self.naive.size.clone().neg();
+        self.naive.value.clone() + pnl
+    }
+
+    /// Get the unrealized PnL at the `mark` price in basis points of the
+    /// absolute notional value, i.e. `10000 * unrealized / |notional|`,
+    /// where the value already booked into the position is not included.
+    ///
+    /// The `mark` is treated to be in the reversed-form
+    /// if the `instrument` is reversed-prefering.
+    /// Return [`None`] if the notional value is zero, or the `mark` is zero in the reversed-form.
+    pub fn pnl_bps(&self, mark: &T) -> Option<T>
+    where
+        T: FromPrimitive,
+    {
+        let notional = self.notional_value().abs();
+        if notional.is_zero() {
+            return None;
+        }
+        let mut pnl = if self.instrument.is_prefer_reversed() {
+            mark.checked_reciprocal()?
         } else {
-            p -= (price.clone(), self.size());
+            mark.clone()
+        };
+        pnl -= &self.naive.price;
+        pnl *= &self.naive.size;
+        pnl *= T::from_u32(10000)?;
+        pnl /= notional;
+        Some(pnl)
+    }
+
+    /// Return the values when the position is closed at each of the given prices,
+    /// the same as calling [`Position::closed`] for each price.
+    /// # Warning
+    /// The `marks` are treated to be in the reversed-form
+    /// if the `instrument` is reversed-prefering.
+    /// # Panic
+    /// Panic if a mark is in the reversed-form and is zero.
+    pub fn value_series(&self, marks: &[T]) -> Vec<T> {
+        let reversed = self.instrument.is_prefer_reversed();
+        marks
+            .iter()
+            .map(|mark| {
+                let mut value = if reversed {
+                    mark.checked_reciprocal()
+                        .expect("zero price cannot be convert into reversed form")
+                } else {
+                    mark.clone()
+                };
+                value -= &self.naive.price;
+                value *= &self.naive.size;
+                value += &self.naive.value;
+                value
+            })
+            .collect()
+    }
+
+    /// Return the value in the `quote` asset when the position is closed at the given price,
+    /// where `rate` is the price of the index asset in the `quote` asset.
+    ///
+    /// The `rate` is only used for quanto instruments,
+    /// for the others this is the same as [`Position::closed`].
+    pub fn closed_with_rate(&self, price: &T, rate: &T) -> T {
+        let mut value = self.closed(price);
+        if self.instrument.is_quanto() {
+            value *= rate;
+        }
+        value
+    }
+
+    /// Return the value in the `quote` asset when the position is closed
+    /// with the price of its instrument provided by `prices`. For quanto instruments,
+    /// the price of `index-quote` spot is also required.
+    /// Return [`None`] if there are missing prices.
+    pub(crate) fn closed_with_provider(&self, prices: &impl PriceProvider<T>) -> Option<T> {
+        let price = prices.price_of(&self.instrument)?;
+        match self.instrument.quanto_index() {
+            Some(index) => {
+                let rate = prices.spot_price(index, self.instrument.quote())?;
+                Some(self.closed_with_rate(&price, &rate))
+            }
+            None => Some(self.closed(&price)),
         }
-        p.value
     }
 
     /// Get the average price of the position,
     /// respecting the reversed preference of its instrument.
     pub fn price(&self) -> Option<T> {
         if self.instrument.is_prefer_reversed() {
-            if self.naive.price.is_zero() {
-                None
-            } else {
-                Some({
-                    let mut v = T::one();
-                    v /= &self.naive.price;
-                    v
-                })
-            }
+            self.naive.price.checked_reciprocal()
         } else {
             Some(self.naive.price.clone())
         }
@@ -92,12 +343,46 @@ where
 
     /// Calculate the notional value of the position.
     /// Note that the notional value of a short position will be negative.
+    /// # Warning
+    /// The multiplication follows the semantics of `T`, so it may overflow
+    /// (wrap or panic) for integers. See [`Position::try_notional_value`].
     pub fn notional_value(&self) -> T {
         let mut value = self.naive.price.clone();
         value *= &self.naive.size;
         value
     }
 
+    /// Calculate the notional value of the position with checked multiplication.
+    /// Return [`None`] if overflow occurred.
+    pub fn try_notional_value(&self) -> Option<T>
+    where
+        T: CheckedMul,
+    {
+        self.naive.price.checked_mul(&self.naive.size)
+    }
+
+    /// Return the position after the fill of the given `price` and `size`,
+    /// without changing this position.
+    /// # Warning
+    /// This method will respect the reversed-preference,
+    /// so the `price` and `size` are treated to be in the reversed-form
+    /// if the `instrument` is reversed-prefering.
+    pub fn average_with(&self, price: &T, size: &T) -> Position<T, M>
+    where
+        M: Clone,
+    {
+        let naive = if self.instrument.is_prefer_reversed() {
+            self.naive.clone() + Reversed((price.clone(), size.clone()))
+        } else {
+            self.naive.clone() + (price.clone(), size.clone())
+        };
+        Position {
+            instrument: self.instrument.clone(),
+            naive,
+            meta: self.meta.clone(),
+        }
+    }
+
     /// Merge with the other position.
     /// After merging, the `other` will be the default ("zero") position.
     /// # Warning
@@ -110,6 +395,23 @@ where
         }
     }
 
+    /// Merge with the other position as [`Position::merge`] does,
+    /// and return the value realized by the merge.
+    /// After merging, the `other` will be the default ("zero") position.
+    ///
+    /// Unlike [`Position::merge`], the instruments must be identical in all fields,
+    /// not only the symbol, otherwise a [`MergeError`] is returned with both unchanged.
+    pub fn try_merge(&mut self, other: &mut Self) -> Result<T, MergeError> {
+        if !is_identical(&self.instrument, &other.instrument) {
+            return Err(MergeError {
+                lhs: Box::new(self.instrument.clone()),
+                rhs: Box::new(other.instrument.clone()),
+            });
+        }
+        let rhs = core::mem::take(&mut other.naive);
+        Ok(self.naive.add_reporting(rhs).realized)
+    }
+
     /// Take the value of the position.
     #[inline]
     pub fn take(&mut self) -> T {
@@ -124,39 +426,48 @@ where
     /// Panic if `to` is in the reversed-form and is zero.
     pub fn convert(&mut self, to: T) {
         let to = if self.instrument.is_prefer_reversed() {
-            if to.is_zero() {
-                panic!("the price in reversed-form cannot be zero");
-            }
-            T::one() / to
+            to.checked_reciprocal()
+                .expect("the price in reversed-form cannot be zero")
         } else {
             to
         };
         self.naive.convert(to);
     }
 
+    /// Compare with the given position as [`PartialEq`] does, but allow
+    /// the true-form `price`, `size` and `value` to differ by at most `epsilon`,
+    /// see [`NaivePosition::approx_eq`].
+    pub fn approx_eq<N>(&self, other: &Position<T, N>, epsilon: &T) -> bool {
+        self.instrument == other.instrument && self.naive.approx_eq(&other.naive, epsilon)
+    }
+
     /// Is this a zero position whose `size` and `value` are both zero.
     pub fn is_zero(&self) -> bool {
         self.naive.size.is_zero() && self.naive.value.is_zero()
     }
 
-    /// Convert to a position tree.
-    pub fn as_tree(&self) -> PositionTree<'_, T> {
-        PositionTree {
-            asset: self.instrument.quote(),
-            value: T::zero(),
-            positions: HashMap::from([(&self.instrument, self)]),
-            children: HashMap::default(),
+    /// Convert the numeric type of the position with the given function,
+    /// keeping the instrument unchanged.
+    pub fn map<U, F>(self, f: F) -> Position<U, M>
+    where
+        U: PositionNum,
+        F: Fn(T) -> U,
+    {
+        Position {
+            instrument: self.instrument,
+            naive: self.naive.map(f),
+            meta: self.meta,
         }
     }
 }
 
-impl<'a, T: PositionNum> IntoNaivePosition<T> for &'a Position<T> {
+impl<T: PositionNum, M> IntoNaivePosition<T> for &Position<T, M> {
     fn into_naive(self) -> NaivePosition<T> {
         self.naive.clone()
     }
 }
 
-impl<T> fmt::Display for Position<T>
+impl<T, M> fmt::Display for Position<T, M>
 where
     T: PositionNum + fmt::Display,
 {
@@ -175,13 +486,17 @@ where
         let value = self.value();
         if !value.is_zero() {
             let sign = if value.is_negative() { " - " } else { " + " };
-            write!(f, "{sign}{} {}", value.abs(), self.instrument.quote())?;
+            let asset = self
+                .instrument
+                .quanto_index()
+                .unwrap_or_else(|| self.instrument.quote());
+            write!(f, "{sign}{} {asset}", value.abs())?;
         }
         Ok(())
     }
 }
 
-impl<T> PartialEq for Position<T>
+impl<T, M> PartialEq for Position<T, M>
 where
     T: PositionNum,
 {
@@ -190,9 +505,9 @@ where
     }
 }
 
-impl<T> Eq for Position<T> where T: PositionNum {}
+impl<T, M> Eq for Position<T, M> where T: PositionNum {}
 
-impl<T, P> AddAssign<P> for Position<T>
+impl<T, M, P> AddAssign<P> for Position<T, M>
 where
     T: PositionNum,
     P: IntoNaivePosition<T>,
@@ -202,7 +517,48 @@ where
     }
 }
 
-impl<T, P> SubAssign<P> for Position<T>
+impl<T, M> Add<Position<T, M>> for Position<T, M>
+where
+    T: PositionNum,
+{
+    type Output = Self;
+
+    /// Merge with the other position of the same instrument.
+    /// # Panic
+    /// Panic if the other position has different `instrument`.
+    /// # Warning
+    /// Unlike `+`, `+=` treats a `&Position` as a naive position
+    /// and does not check its instrument.
+    fn add(mut self, rhs: Position<T, M>) -> Self::Output {
+        assert!(
+            self.instrument == rhs.instrument,
+            "cannot add positions of different instruments"
+        );
+        self.naive += rhs.naive;
+        self
+    }
+}
+
+impl<'a, T, M> Add<&'a Position<T, M>> for Position<T, M>
+where
+    T: PositionNum,
+{
+    type Output = Self;
+
+    /// Merge with the other position of the same instrument.
+    /// # Panic
+    /// Panic if the other position has different `instrument`.
+    fn add(mut self, rhs: &'a Position<T, M>) -> Self::Output {
+        assert!(
+            self.instrument == rhs.instrument,
+            "cannot add positions of different instruments"
+        );
+        self.naive += rhs.naive.clone();
+        self
+    }
+}
+
+impl<T, M, P> SubAssign<P> for Position<T, M>
 where
     T: PositionNum,
     P: IntoNaivePosition<T>,
@@ -212,7 +568,7 @@ where
     }
 }
 
-impl<T> Neg for Position<T>
+impl<T, M> Neg for Position<T, M>
 where
     T: PositionNum,
 {
@@ -220,14 +576,39 @@ where
 
     fn neg(self) -> Self::Output {
         Self {
-            instrument: self.instrument.clone(),
+            instrument: self.instrument,
             naive: self.naive.neg(),
+            meta: self.meta,
         }
     }
 }
 
-impl<T> AsRef<Position<T>> for Position<T> {
-    fn as_ref(&self) -> &Position<T> {
+impl<T, M> MulAssign<T> for Position<T, M>
+where
+    T: PositionNum,
+{
+    /// Scale the `size` and `value` of the position, keeping the `price` unchanged.
+    fn mul_assign(&mut self, rhs: T) {
+        self.naive.size *= &rhs;
+        self.naive.value *= rhs;
+    }
+}
+
+impl<T, M> Mul<T> for Position<T, M>
+where
+    T: PositionNum,
+{
+    type Output = Self;
+
+    /// Scale the `size` and `value` of the position, keeping the `price` unchanged.
+    fn mul(mut self, rhs: T) -> Self::Output {
+        self *= rhs;
+        self
+    }
+}
+
+impl<T, M> AsRef<Position<T, M>> for Position<T, M> {
+    fn as_ref(&self) -> &Position<T, M> {
         self
     }
 }
@@ -302,14 +683,41 @@ where
         }
     }
 
+    /// Get the value of the bucket in its own asset when all the positions are closed
+    /// at the prices in `prices`, i.e. the sum of the `value` and the closed values.
+    /// For quanto instruments, the prices of the `index-quote` spots are also required.
+    /// Return [`None`] if there are missing prices.
+    pub fn total_value(&self, prices: &HashMap<Symbol, T>) -> Option<T> {
+        self.positions
+            .values()
+            .map(|p| p.closed_with_provider(prices))
+            .try_fold(self.value.clone(), |acc, x| Some(acc + x?))
+    }
+
     fn concentrate(&mut self) {
         let value = self
             .positions
             .values_mut()
+            .filter(|p| !p.instrument.is_quanto())
             .map(|p| p.take())
             .fold(T::zero(), T::add);
         self.value += value;
     }
+
+    fn map<U, F>(self, f: F) -> SingleValue<U>
+    where
+        U: PositionNum,
+        F: Fn(T) -> U,
+    {
+        SingleValue {
+            value: f(self.value),
+            positions: self
+                .positions
+                .into_iter()
+                .map(|(symbol, p)| (symbol, p.map(&f)))
+                .collect(),
+        }
+    }
 }
 
 impl<T> AddAssign<&Self> for SingleValue<T>
@@ -341,27 +749,86 @@ where
 impl<T> Eq for SingleValue<T> where T: PositionNum {}
 
 /// A table of positions.
+///
+/// It is serialized in a compact form, with a list of the values of the assets
+/// (including the empty buckets) and a flat list of the positions,
+/// which are put back into the buckets of their quote assets on load.
+/// The nested form of the earlier versions can still be deserialized.
 #[derive(Debug, Clone)]
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Positions<T> {
     values: HashMap<Asset, SingleValue<T>>,
+    fees: HashMap<Asset, T>,
+    ledger: Option<Vec<LedgerEntry<T>>>,
 }
 
 impl<T> Default for Positions<T> {
     fn default() -> Self {
         Self {
             values: Default::default(),
+            fees: Default::default(),
+            ledger: None,
         }
     }
 }
 
 impl<T> Positions<T> {
+    /// Create an empty table with capacity for at least `capacity` assets.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            values: HashMap::with_capacity(capacity),
+            fees: HashMap::default(),
+            ledger: None,
+        }
+    }
+
+    /// Take an immutable snapshot of the table that can be shared with
+    /// the readers (e.g. a valuation thread) while the writer keeps updating
+    /// the table itself.
+    ///
+    /// The snapshot is a deep clone of the table, so the cost is linear in
+    /// its size, while sharing it afterwards is cheap. To publish the updates,
+    /// take a new snapshot and replace the shared one (copy-on-write).
+    pub fn snapshot(&self) -> Arc<Self>
+    where
+        T: Clone,
+    {
+        Arc::new(self.clone())
+    }
+
+    /// Get the fees charged by [`Positions::charge_fee`], keyed by asset.
+    pub fn fees(&self) -> &HashMap<Asset, T> {
+        &self.fees
+    }
+
+    /// Get the number of assets the table can hold without reallocating.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.values.capacity()
+    }
+
+    /// Reserve capacity for at least `additional` more assets.
+    #[inline]
+    pub fn reserve(&mut self, additional: usize) {
+        self.values.reserve(additional);
+    }
+
     /// Create an iterator of [`SingleValue`]s.
     #[inline]
     pub fn iter(&self) -> impl Iterator<Item = (&Asset, &SingleValue<T>)> {
         self.values.iter()
     }
 
+    /// Create an iterator of the mutable references of all the positions
+    /// across the buckets, with their symbols.
+    ///
+    /// Unlike [`Positions::iter`], the positions are flattened, and the instrument
+    /// can be read from [`Position::instrument`].
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&Symbol, &mut Position<T>)> {
+        self.values
+            .values_mut()
+            .flat_map(|sv| sv.positions.iter_mut())
+    }
+
     /// Get the number of [`SingleValue`]s.
     #[inline]
     pub fn len(&self) -> usize {
@@ -384,6 +851,61 @@ where
         Expr::new(self)
     }
 
+    /// Compare the tables as if the zero positions, the empty buckets
+    /// and the zero fees were pruned, without mutating them.
+    /// This is a comparison of the economic states, while [`PartialEq`] also
+    /// compares the leftover entries.
+    pub fn eq_canonical(&self, other: &Self) -> bool {
+        fn subset<T: PositionNum>(lhs: &Positions<T>, rhs: &Positions<T>) -> bool {
+            let zero = T::zero();
+            let empty = SingleValue::default();
+            lhs.values.iter().all(|(asset, sv)| {
+                let other = rhs.values.get(asset).unwrap_or(&empty);
+                sv.value == other.value
+                    && sv
+                        .positions
+                        .iter()
+                        .all(|(symbol, p)| match other.positions.get(symbol) {
+                            Some(q) => p == q,
+                            None => p.is_zero(),
+                        })
+            }) && lhs
+                .fees
+                .iter()
+                .all(|(asset, fee)| rhs.fees.get(asset).unwrap_or(&zero) == fee)
+        }
+        subset(self, other) && subset(other, self)
+    }
+
+    /// Get the distinct symbols of the reference instruments of the
+    /// expression (see [`Expr::instruments`]) in sorted order,
+    /// e.g. for subscribing to the market data of exactly these symbols.
+    pub fn subscribe_symbols(&self, root: &Asset) -> Vec<Symbol> {
+        let mut symbols = self
+            .as_expr()
+            .instruments(root)
+            .map(|inst| inst.as_symbol().clone())
+            .collect::<Vec<_>>();
+        symbols.sort();
+        symbols.dedup();
+        symbols
+    }
+
+    /// Consume the table and create an iterator of all the non-zero positions
+    /// across the buckets, with their symbols. The positions are moved out,
+    /// while the values and fees are dropped.
+    pub fn into_positions(self) -> impl Iterator<Item = (Symbol, Position<T>)> {
+        self.values
+            .into_values()
+            .flat_map(|sv| sv.positions)
+            .filter(|(_, p)| !p.is_zero())
+    }
+
+    /// Convert into an owned positions expression.
+    pub fn into_expr(self) -> OwnedExpr<T> {
+        OwnedExpr(self)
+    }
+
     /// Convert to a position tree, using the given asset as root.
     pub fn as_tree<'a>(&'a self, root: &'a Asset) -> PositionTree<'a, T> {
         let children = self
@@ -423,8 +945,78 @@ where
         }
     }
 
+    /// Evaluate the whole positions in the given asset, which need not be held,
+    /// the same as building [`Positions::as_tree`] and calling [`PositionTree::eval`].
+    /// Return [`None`] if there are missing prices.
+    pub fn eval_in(&self, root: &Asset, prices: &impl PriceProvider<T>) -> Option<T> {
+        self.as_tree(root).eval(prices)
+    }
+
+    /// Evaluate the positions in `root` with the price of `vary` swept over `points`,
+    /// i.e. the payoff of the book as a function of a single price, holding the
+    /// other prices in `base_prices` fixed.
+    ///
+    /// If `vary` is a spot, the price of its reversed spot (e.g. `USDT-BTC` for `BTC-USDT`)
+    /// is adjusted to the reciprocal when it is in `base_prices`; every other price,
+    /// including the derivatives and cross pairs of the same asset, is held as given.
+    /// The `points` are treated to be in the reversed-form if `vary` is reversed-prefering.
+    /// A point is evaluated to [`None`] if there are missing prices,
+    /// or if it is zero while the reversed spot is to be adjusted.
+    pub fn payoff_curve(
+        &self,
+        vary: &Instrument,
+        base_prices: &HashMap<Symbol, T>,
+        points: &[T],
+        root: &Asset,
+    ) -> Vec<Option<T>> {
+        let reversed = vary
+            .as_symbol()
+            .to_reversed_symbol()
+            .filter(|symbol| base_prices.contains_key(symbol));
+        let mut prices = base_prices.clone();
+        let tree = self.as_tree(root);
+        points
+            .iter()
+            .map(|point| {
+                if let Some(symbol) = reversed.as_ref() {
+                    prices.insert(symbol.clone(), point.checked_reciprocal()?);
+                }
+                prices.insert(vary.as_symbol().clone(), point.clone());
+                tree.eval(&prices)
+            })
+            .collect()
+    }
+
     /// Insert a position.
     pub fn insert_position(&mut self, position: Position<T>) -> &mut Self {
+        let Some(ledger) = self.ledger.as_mut() else {
+            return self.add_position(position);
+        };
+        let sv = self
+            .values
+            .entry(position.instrument.quote().clone())
+            .or_default();
+        let symbol = position.instrument.as_symbol();
+        if let Some(p) = sv.positions.get_mut(symbol) {
+            let report = p.naive.add_reporting(position.naive);
+            if !report.realized.is_zero() {
+                let asset = p
+                    .instrument
+                    .quanto_index()
+                    .unwrap_or_else(|| p.instrument.quote());
+                ledger.push(LedgerEntry {
+                    asset: asset.clone(),
+                    amount: report.realized,
+                    reason: LedgerReason::Realize(symbol.clone()),
+                });
+            }
+        } else {
+            sv.positions.insert(symbol.clone(), position);
+        }
+        self
+    }
+
+    fn add_position(&mut self, position: Position<T>) -> &mut Self {
         self.values
             .entry(position.instrument.quote().clone())
             .or_default()
@@ -434,6 +1026,11 @@ where
 
     /// Insert an value.
     pub fn insert_value(&mut self, value: T, asset: &Asset) -> &mut Self {
+        self.record(asset, &value, LedgerReason::Insert);
+        self.add_value(value, asset)
+    }
+
+    fn add_value(&mut self, value: T, asset: &Asset) -> &mut Self {
         if let Some(sv) = self.values.get_mut(asset) {
             sv.value += value;
         } else {
@@ -456,6 +1053,19 @@ where
             .get(instrument.as_symbol())
     }
 
+    /// Get the reference of the position of the same market as the given instrument,
+    /// see [`Instrument::is_same_market`], e.g. the position of `USDT-BTC` for `BTC-USDT`.
+    /// The position of the instrument itself is preferred.
+    ///
+    /// This can be used to avoid holding a spot market under two buckets
+    /// when the feeds name it in different orders.
+    pub fn get_same_market(&self, instrument: &Instrument) -> Option<&Position<T>> {
+        self.get_position(instrument).or_else(|| {
+            let reversed = instrument.as_symbol().to_reversed_symbol()?;
+            self.values.get(instrument.base())?.positions.get(&reversed)
+        })
+    }
+
     /// Get the reference of the value of the given asset.
     pub fn get_value(&self, asset: &Asset) -> Option<&T> {
         Some(&self.values.get(asset)?.value)
@@ -469,509 +1079,4210 @@ where
             .get_mut(instrument.as_symbol())
     }
 
-    /// Get the mutable reference of the value of the given asset.
-    pub fn get_value_mut(&mut self, asset: &Asset) -> Option<&mut T> {
-        Some(&mut self.values.get_mut(asset)?.value)
-    }
-
-    /// Concentrate the values.
-    pub fn concentrate(&mut self) {
-        for sv in self.values.values_mut() {
-            sv.concentrate();
+    /// Create an iterator of all the assets referenced by the positions table,
+    /// including the assets of the values and the base, quote (and index) assets
+    /// of the instruments. Each asset is yielded only once.
+    pub fn assets(&self) -> impl Iterator<Item = &Asset> {
+        let mut assets = HashSet::new();
+        for (asset, sv) in self.values.iter() {
+            assets.insert(asset);
+            for p in sv.positions.values() {
+                assets.insert(p.instrument.base());
+                assets.insert(p.instrument.quote());
+                assets.extend(p.instrument.quanto_index());
+            }
         }
+        assets.into_iter()
     }
-}
-
-impl<T> IntoIterator for Positions<T> {
-    type Item = (Asset, SingleValue<T>);
 
-    type IntoIter = <HashMap<Asset, SingleValue<T>> as IntoIterator>::IntoIter;
+    /// Get the total size of the positions whose base asset is `base`,
+    /// including the value of `base` itself.
+    ///
+    /// The sizes of the positions respect the reversed preferences of their instruments.
+    pub fn total_size_of_base(&self, base: &Asset) -> T {
+        self.values.iter().fold(T::zero(), |mut acc, (asset, sv)| {
+            if asset == base {
+                acc += &sv.value;
+            }
+            for p in sv.positions.values() {
+                if p.instrument.base() == base {
+                    acc += p.size();
+                }
+            }
+            acc
+        })
+    }
 
-    #[inline]
-    fn into_iter(self) -> Self::IntoIter {
-        self.values.into_iter()
+    /// Convert the price of every position to its mark in `prices` with
+    /// [`Position::convert`], booking the difference into its value,
+    /// so that the values are mark-to-market afterwards.
+    ///
+    /// The prices are treated to be in the reversed-form for the reversed-prefering
+    /// instruments. The positions whose prices are missing (or zero in the reversed-form)
+    /// are skipped and returned if `skip_invalid` is `true`; otherwise the first of them
+    /// is returned as an error and nothing is changed.
+    pub fn convert_all(
+        &mut self,
+        prices: &HashMap<Symbol, T>,
+        skip_invalid: bool,
+    ) -> Result<Vec<Symbol>, ConvertError> {
+        let mut skipped = Vec::new();
+        for p in self.values.values().flat_map(|sv| sv.positions.values()) {
+            let symbol = p.instrument.as_symbol();
+            let err = match prices.get(symbol) {
+                None => ConvertError::MissingPrice(symbol.clone()),
+                Some(price) if p.instrument.is_prefer_reversed() && price.is_zero() => {
+                    ConvertError::ZeroPrice(symbol.clone())
+                }
+                Some(_) => continue,
+            };
+            if !skip_invalid {
+                return Err(err);
+            }
+            skipped.push(symbol.clone());
+        }
+        for p in self
+            .values
+            .values_mut()
+            .flat_map(|sv| sv.positions.values_mut())
+        {
+            if skipped.contains(p.instrument.as_symbol()) {
+                continue;
+            }
+            if let Some(price) = prices.get(p.instrument.as_symbol()) {
+                p.convert(price.clone());
+            }
+        }
+        Ok(skipped)
     }
-}
 
-impl<T> PartialEq for Positions<T>
-where
-    T: PositionNum,
-{
-    fn eq(&self, other: &Self) -> bool {
-        self.values == other.values
+    /// Close the net exposure of the positions whose base asset is `base`
+    /// at the given prices, e.g. a long spot leg and a short swap leg of `BTC`,
+    /// so that their net size becomes zero.
+    ///
+    /// Only the legs in the direction of the net exposure are reduced, the largest
+    /// (by absolute size) first. The sizes are in the true form, the values of `base`
+    /// and the quanto positions are not counted.
+    /// The prices are treated to be in the reversed-form for the reversed-prefering
+    /// instruments, as in [`Position::closed`].
+    ///
+    /// Return the realized value converted to the `base` asset with the closing prices,
+    /// or [`None`] if there are missing or zero prices, in which case nothing is changed.
+    pub fn flatten_base(&mut self, base: &Asset, prices: &HashMap<Symbol, T>) -> Option<T> {
+        let mut legs = self
+            .values
+            .values_mut()
+            .flat_map(|sv| sv.positions.values_mut())
+            .filter(|p| {
+                p.instrument.base() == base && !p.instrument.is_quanto() && !p.naive.size.is_zero()
+            })
+            .collect::<Vec<_>>();
+        let net = legs
+            .iter()
+            .fold(T::zero(), |acc, p| acc + p.naive.size.clone());
+        if net.is_zero() {
+            return Some(T::zero());
+        }
+        legs.retain(|p| p.naive.size.is_positive() == net.is_positive());
+        legs.sort_by(|lhs, rhs| {
+            rhs.naive
+                .size
+                .abs()
+                .partial_cmp(&lhs.naive.size.abs())
+                .unwrap_or(core::cmp::Ordering::Equal)
+                .then_with(|| lhs.instrument.as_symbol().cmp(rhs.instrument.as_symbol()))
+        });
+        let mut plan = Vec::new();
+        let mut remaining = net.abs();
+        for p in legs {
+            if remaining.is_zero() {
+                break;
+            }
+            let price = prices.get(p.instrument.as_symbol())?;
+            let price = if p.instrument.is_prefer_reversed() {
+                price.checked_reciprocal()?
+            } else if price.is_zero() {
+                return None;
+            } else {
+                price.clone()
+            };
+            let size = p.naive.size.abs();
+            let size = if size < remaining {
+                size
+            } else {
+                remaining.clone()
+            };
+            remaining -= &size;
+            plan.push((p, price, size));
+        }
+        let mut realized = T::zero();
+        for (p, price, size) in plan {
+            let size = if net.is_positive() { -size } else { size };
+            let mut value = p.naive.add_reporting((price.clone(), size)).realized;
+            value /= price;
+            realized += value;
+        }
+        Some(realized)
     }
-}
 
-impl<T> Eq for Positions<T> where T: PositionNum {}
+    /// Get the sum of the absolute notional values of the positions
+    /// at the given prices, converted to the `root` asset.
+    /// Return [`None`] if there are missing prices.
+    pub fn gross_notional(&self, root: &Asset, prices: &HashMap<Symbol, T>) -> Option<T> {
+        self.values
+            .iter()
+            .map(|(asset, sv)| {
+                let mut notional = sv
+                    .positions
+                    .values()
+                    .map(|p| {
+                        let inst = &p.instrument;
+                        let price = prices.get(inst.as_symbol())?;
+                        let mut notional = if inst.is_prefer_reversed() {
+                            price.checked_reciprocal()?
+                        } else {
+                            price.clone()
+                        };
+                        notional *= &p.naive.size;
+                        if let Some(index) = inst.quanto_index() {
+                            notional *= prices.get(&Symbol::spot(index, inst.quote()))?;
+                        }
+                        Some(notional.abs())
+                    })
+                    .try_fold(T::zero(), |acc, x| Some(acc + x?))?;
+                if asset != root {
+                    notional *= prices.get(&Symbol::spot(asset, root))?;
+                }
+                Some(notional)
+            })
+            .try_fold(T::zero(), |acc, x| Some(acc + x?))
+    }
 
-impl<T> AddAssign<&Self> for Positions<T>
-where
-    T: PositionNum,
-{
-    fn add_assign(&mut self, rhs: &Self) {
-        for (asset, rhs) in rhs.values.iter() {
-            if let Some(lhs) = self.values.get_mut(asset) {
-                *lhs += rhs;
-            } else {
-                self.values.insert(asset.clone(), rhs.clone());
+    /// Get the leverage, i.e. the gross notional value divided by the equity,
+    /// both in the `root` asset.
+    /// Return [`None`] if there are missing prices or the equity is zero.
+    pub fn leverage(&self, root: &Asset, prices: &HashMap<Symbol, T>) -> Option<T> {
+        let equity = self.as_expr().eval(root, prices)?;
+        if equity.is_zero() {
+            return None;
+        }
+        Some(self.gross_notional(root, prices)? / equity)
+    }
+
+    /// Compute the spot trades needed to rebalance the portfolio to the target
+    /// `weights` of the equity, both valued in the `root` asset.
+    ///
+    /// The current exposure of an asset is its [`Positions::total_size_of_base`],
+    /// and its rate is the price of the `asset-root` spot (or the reciprocal of the
+    /// `root-asset` one, see [`cross_rate`]). The returned trades are the size deltas
+    /// of the `asset-root` spots, sorted by asset, with the zero ones omitted.
+    /// The `root` asset itself takes up the rest of the equity, so its weight is ignored.
+    /// Return [`None`] if there are missing prices or the rate of a target asset is zero.
+    pub fn rebalance_to(
+        &self,
+        weights: &HashMap<Asset, T>,
+        root: &Asset,
+        prices: &HashMap<Symbol, T>,
+    ) -> Option<Vec<(Instrument, T)>> {
+        let equity = self.as_expr().eval(root, prices)?;
+        let mut assets = weights.keys().filter(|a| *a != root).collect::<Vec<_>>();
+        assets.sort();
+        let mut trades = Vec::with_capacity(assets.len());
+        for asset in assets {
+            let rate = cross_rate(prices, asset, root, root)?;
+            if rate.is_zero() {
+                return None;
+            }
+            let mut delta = weights[asset].clone();
+            delta *= &equity;
+            delta /= rate;
+            delta -= self.total_size_of_base(asset);
+            if !delta.is_zero() {
+                trades.push((Instrument::spot(asset, root), delta));
             }
         }
+        Some(trades)
     }
-}
 
-impl<T> AddAssign for Positions<T>
-where
-    T: PositionNum,
-{
-    fn add_assign(&mut self, rhs: Self) {
-        *self += &rhs;
+    /// Get the mutable reference of the position of the given instrument,
+    /// inserting a zero position if it does not exist.
+    pub fn entry_position(&mut self, instrument: &Instrument) -> &mut Position<T> {
+        self.values
+            .entry(instrument.quote().clone())
+            .or_default()
+            .positions
+            .entry(instrument.as_symbol().clone())
+            .or_insert_with(|| Position::zero(instrument))
     }
-}
 
-impl<T> Add for Positions<T>
-where
-    T: PositionNum,
-{
-    type Output = Self;
+    /// Get the mutable reference of the value of the given asset.
+    pub fn get_value_mut(&mut self, asset: &Asset) -> Option<&mut T> {
+        Some(&mut self.values.get_mut(asset)?.value)
+    }
 
-    fn add(mut self, rhs: Self) -> Self::Output {
-        self += rhs;
-        self
+    /// Close all the positions at their marks in `prices` and sweep their values
+    /// into the cash of their buckets, i.e. [`Position::closed`] (or
+    /// [`Position::closed_with_rate`] for quanto instruments, which requires the price
+    /// of the `index-quote` spot) followed by [`Positions::concentrate`],
+    /// but the closed positions are removed.
+    ///
+    /// The marks are treated to be in the reversed-form for the reversed-prefering
+    /// instruments. Return the cash swept into each asset, or [`MissingPrice`]
+    /// with the book unchanged if a price of an open position is missing.
+    pub fn close_all(
+        &mut self,
+        prices: &HashMap<Symbol, T>,
+    ) -> Result<HashMap<Asset, T>, MissingPrice> {
+        let mut swept = Vec::new();
+        for (asset, sv) in self.values.iter() {
+            for (symbol, p) in sv.positions.iter() {
+                let inst = &p.instrument;
+                let mut value = if p.naive.size.is_zero() {
+                    p.naive.value.clone()
+                } else {
+                    let price = prices.get(symbol).ok_or_else(|| MissingPrice::new(inst))?;
+                    p.closed(price)
+                };
+                if let Some(index) = inst.quanto_index() {
+                    let spot = Instrument::spot(index, inst.quote());
+                    value *= prices
+                        .get(spot.as_symbol())
+                        .ok_or_else(|| MissingPrice::new(&spot))?;
+                }
+                swept.push((asset.clone(), symbol.clone(), value));
+            }
+        }
+        let mut cash = HashMap::default();
+        for (asset, symbol, value) in swept {
+            let sv = self.values.get_mut(&asset).expect("the bucket must exist");
+            sv.positions.remove(&symbol);
+            if value.is_zero() {
+                continue;
+            }
+            sv.value += &value;
+            self.record(&asset, &value, LedgerReason::Realize(symbol));
+            *cash.entry(asset).or_insert_with(T::zero) += value;
+        }
+        Ok(cash)
     }
-}
 
-impl<T> AddAssign<Position<T>> for Positions<T>
-where
-    T: PositionNum,
-{
-    fn add_assign(&mut self, rhs: Position<T>) {
-        self.insert_position(rhs);
+    /// Concentrate the values.
+    ///
+    /// The values of the positions of quanto instruments are denominated in their
+    /// index assets (see [`Instrument::quanto`]), so they are kept in the positions.
+    pub fn concentrate(&mut self) {
+        let Some(ledger) = self.ledger.as_mut() else {
+            for sv in self.values.values_mut() {
+                sv.concentrate();
+            }
+            return;
+        };
+        for (asset, sv) in self.values.iter_mut() {
+            for (symbol, p) in sv.positions.iter_mut() {
+                if p.instrument.is_quanto() {
+                    continue;
+                }
+                let value = p.take();
+                if !value.is_zero() {
+                    ledger.push(LedgerEntry {
+                        asset: asset.clone(),
+                        amount: value.clone(),
+                        reason: LedgerReason::Concentrate(symbol.clone()),
+                    });
+                    sv.value += value;
+                }
+            }
+        }
     }
-}
 
-impl<'a, T> AddAssign<(T, &'a Asset)> for Positions<T>
-where
-    T: PositionNum,
-{
-    fn add_assign(&mut self, (value, asset): (T, &'a Asset)) {
-        self.insert_value(value, asset);
+    /// Partition the positions by the prefixes of their symbols
+    /// (see [`Symbol::derivative_prefix`]).
+    ///
+    /// The spots are grouped under [`Symbol::SPOT_PREFIX`], together with the values,
+    /// so that the sum of the groups is equal to the table.
+    pub fn group_by_prefix(&self) -> HashMap<Str, Positions<T>> {
+        let mut groups: HashMap<Str, Positions<T>> = HashMap::default();
+        for (asset, sv) in self.values.iter() {
+            if !sv.value.is_zero() {
+                groups
+                    .entry(Symbol::SPOT_PREFIX)
+                    .or_default()
+                    .insert_value(sv.value.clone(), asset);
+            }
+            for p in sv.positions.values() {
+                let prefix = p
+                    .instrument
+                    .as_symbol()
+                    .derivative_prefix()
+                    .map(Str::new)
+                    .unwrap_or(Symbol::SPOT_PREFIX);
+                groups.entry(prefix).or_default().insert_position(p.clone());
+            }
+        }
+        groups
     }
-}
 
-impl<'a, T> AddAssign<(T, T, &'a Instrument)> for Positions<T>
-where
-    T: PositionNum,
-{
-    fn add_assign(&mut self, (price, size, instrument): (T, T, &'a Instrument)) {
-        self.insert_position(Position::new(instrument.clone(), (price, size)));
+    /// Treat the positions with `|size| < size_epsilon` as closed: their values are
+    /// folded into the values of their quote assets (or of their index assets
+    /// for quanto instruments, see [`Instrument::quanto`]) and they are dropped.
+    /// Then the values with `|value| < value_epsilon` are set to zero,
+    /// and the [`SingleValue`]s left with no positions and zero value are removed.
+    pub fn prune_dust(&mut self, size_epsilon: &T, value_epsilon: &T) {
+        let mut index_values = Vec::new();
+        for sv in self.values.values_mut() {
+            let mut value = T::zero();
+            sv.positions.retain(|_, p| {
+                if p.naive.size.abs() >= *size_epsilon {
+                    return true;
+                }
+                match p.instrument.quanto_index() {
+                    Some(index) => index_values.push((index.clone(), p.naive.value.clone())),
+                    None => value += &p.naive.value,
+                }
+                false
+            });
+            sv.value += value;
+        }
+        for (asset, value) in index_values {
+            self.add_value(value, &asset);
+        }
+        self.values.retain(|_, sv| {
+            if sv.value.abs() < *value_epsilon {
+                sv.value = T::zero();
+            }
+            !(sv.is_empty() && sv.value.is_zero())
+        });
     }
-}
 
-impl<'a, T> AddAssign<(T, T, T, &'a Instrument)> for Positions<T>
-where
-    T: PositionNum,
-{
-    fn add_assign(&mut self, (price, size, value, instrument): (T, T, T, &'a Instrument)) {
-        self.insert_position(Position::new(instrument.clone(), (price, size, value)));
+    /// Re-index the positions by their instruments, merging the positions of the
+    /// same instrument, and report the symbols shared by different instruments.
+    ///
+    /// A position is moved to the [`SingleValue`] of its quote asset and keyed by its
+    /// symbol, e.g. after deserializing a table edited by hand. The positions involved
+    /// in a [`SymbolConflict`] are left where they were instead of being merged.
+    pub fn merge_duplicate_symbols(&mut self) -> Vec<SymbolConflict> {
+        let mut groups: HashMap<Symbol, Vec<(Asset, Symbol, Position<T>)>> = HashMap::default();
+        for (asset, sv) in self.values.iter_mut() {
+            for (key, p) in core::mem::take(&mut sv.positions) {
+                groups
+                    .entry(p.instrument.as_symbol().clone())
+                    .or_default()
+                    .push((asset.clone(), key, p));
+            }
+        }
+        let mut conflicts = Vec::new();
+        for (symbol, group) in groups {
+            let mut instruments: Vec<Instrument> = Vec::new();
+            for (_, _, p) in group.iter() {
+                if !instruments
+                    .iter()
+                    .any(|inst| is_identical(inst, &p.instrument))
+                {
+                    instruments.push(p.instrument.clone());
+                }
+            }
+            if instruments.len() == 1 {
+                for (_, _, p) in group {
+                    self.add_position(p);
+                }
+            } else {
+                for (asset, key, p) in group {
+                    if let Some(sv) = self.values.get_mut(&asset) {
+                        sv.positions.insert(key, p);
+                    }
+                }
+                conflicts.push(SymbolConflict {
+                    symbol,
+                    instruments,
+                });
+            }
+        }
+        conflicts
     }
-}
 
-impl<'a, T> AddAssign<Reversed<(T, T, &'a Instrument)>> for Positions<T>
-where
-    T: PositionNum,
-{
-    fn add_assign(
-        &mut self,
-        Reversed((price, size, instrument)): Reversed<(T, T, &'a Instrument)>,
-    ) {
-        self.insert_position(Position::new(instrument.clone(), Reversed((price, size))));
+    /// Remove the positions matching the given predicate from the table,
+    /// and return them as a new [`Positions`].
+    ///
+    /// The [`SingleValue`]s left with no positions and zero value are removed.
+    pub fn extract<F>(&mut self, mut f: F) -> Positions<T>
+    where
+        F: FnMut(&Instrument, &Position<T>) -> bool,
+    {
+        let mut extracted = Positions::default();
+        let mut empties = Vec::new();
+        for (asset, sv) in self.values.iter_mut() {
+            let symbols = sv
+                .positions
+                .iter()
+                .filter(|(_, p)| (f)(&p.instrument, p))
+                .map(|(symbol, _)| symbol.clone())
+                .collect::<Vec<_>>();
+            if symbols.is_empty() {
+                continue;
+            }
+            for symbol in symbols {
+                if let Some(p) = sv.positions.remove(&symbol) {
+                    extracted.insert_position(p);
+                }
+            }
+            if sv.is_empty() && sv.value.is_zero() {
+                empties.push(asset.clone());
+            }
+        }
+        for asset in empties {
+            self.values.remove(&asset);
+        }
+        extracted
     }
-}
 
-impl<'a, T> AddAssign<Reversed<(T, T, T, &'a Instrument)>> for Positions<T>
-where
-    T: PositionNum,
-{
-    fn add_assign(
-        &mut self,
-        Reversed((price, size, value, instrument)): Reversed<(T, T, T, &'a Instrument)>,
-    ) {
-        self.insert_position(Position::new(
-            instrument.clone(),
-            Reversed((price, size, value)),
-        ));
+    /// Apply a split (or redenomination) of the given asset,
+    /// where one old unit becomes `ratio` new units.
+    ///
+    /// The value held in the asset is multiplied by `ratio`. For the positions whose
+    /// base asset is the asset, the sizes are multiplied by `ratio` and the prices
+    /// are divided by `ratio`; for the positions whose quote asset is the asset,
+    /// the prices and values are multiplied by `ratio`. So the notional values
+    /// are kept unchanged in the old units. The prices and values of the positions
+    /// of quanto instruments are denominated in their index assets instead
+    /// (see [`Instrument::quanto`]), so they are multiplied only if the index is the asset.
+    /// # Panic
+    /// `ratio` must not be zero.
+    pub fn apply_split(&mut self, asset: &Asset, ratio: &T) {
+        assert!(!ratio.is_zero(), "the split ratio cannot be zero");
+        if let Some(fee) = self.fees.get_mut(asset) {
+            *fee *= ratio;
+        }
+        for (quote, sv) in self.values.iter_mut() {
+            let is_quote = quote == asset;
+            if is_quote {
+                sv.value *= ratio;
+            }
+            for p in sv.positions.values_mut() {
+                if p.instrument.base() == asset {
+                    p.naive.size *= ratio;
+                    p.naive.price /= ratio;
+                }
+                let denominated = match p.instrument.quanto_index() {
+                    Some(index) => index == asset,
+                    None => is_quote,
+                };
+                if denominated {
+                    p.naive.price *= ratio;
+                    p.naive.value *= ratio;
+                }
+            }
+        }
     }
-}
 
-impl<T> From<Position<T>> for Positions<T>
-where
-    T: PositionNum,
-{
-    fn from(p: Position<T>) -> Self {
-        let asset = p.instrument.quote().clone();
-        let inst = p.instrument.as_symbol().clone();
-        let sv = SingleValue {
-            value: T::zero(),
-            positions: HashMap::from([(inst, p)]),
-        };
-        Self {
-            values: HashMap::from([(asset, sv)]),
+    /// Rename the asset `from` to `to`, merging into `to` if it exists.
+    ///
+    /// The instruments referencing `from` are rebuilt with `to`,
+    /// including the symbols of the spots.
+    pub fn rename_asset(&mut self, from: &Asset, to: &Asset) {
+        if from == to {
+            return;
+        }
+        if let Some(fee) = self.fees.remove(from) {
+            *self.fees.entry(to.clone()).or_insert_with(T::zero) += fee;
+        }
+        let values = core::mem::take(&mut self.values);
+        for (asset, sv) in values {
+            let asset = if asset == *from { to.clone() } else { asset };
+            self.add_value(sv.value, &asset);
+            for p in sv.positions.into_values() {
+                let instrument = p.instrument.with_renamed_asset(from, to);
+                self.add_position(Position {
+                    instrument,
+                    naive: p.naive,
+                    meta: (),
+                });
+            }
         }
     }
-}
 
-impl<T> fmt::Display for SingleValue<T>
-where
-    T: fmt::Display + PositionNum,
-{
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        const MIDDLE: &str = "├ ";
-        const LAST: &str = "└ ";
-        let len = self.positions.len();
-        for (idx, (inst, p)) in self.positions.iter().enumerate() {
-            if p.is_zero() {
-                continue;
+    /// Convert the numeric type of the positions with the given function.
+    pub fn map<U, F>(self, f: F) -> Positions<U>
+    where
+        U: PositionNum,
+        F: Fn(T) -> U,
+    {
+        Positions {
+            values: self
+                .values
+                .into_iter()
+                .map(|(asset, sv)| (asset, sv.map(&f)))
+                .collect(),
+            fees: self
+                .fees
+                .into_iter()
+                .map(|(asset, fee)| (asset, f(fee)))
+                .collect(),
+            ledger: self.ledger.map(|ledger| {
+                ledger
+                    .into_iter()
+                    .map(|entry| LedgerEntry {
+                        asset: entry.asset,
+                        amount: f(entry.amount),
+                        reason: entry.reason,
+                    })
+                    .collect()
+            }),
+        }
+    }
+
+    /// Get the sum of the values of the assets converted to the `root` asset,
+    /// ignoring the positions. The values are converted with the `asset-root` spots
+    /// or the reciprocals of the `root-asset` spots (see [`cross_rate`]).
+    /// Return [`None`] if there are missing prices.
+    pub fn cash_in(&self, root: &Asset, prices: &HashMap<Symbol, T>) -> Option<T> {
+        self.values
+            .iter()
+            .map(|(asset, sv)| {
+                let mut value = sv.value.clone();
+                value *= cross_rate(prices, asset, root, root)?;
+                Some(value)
+            })
+            .try_fold(T::zero(), |acc, x| Some(acc + x?))
+    }
+
+    /// Compare the cash of each asset (the value together with the values of the positions)
+    /// with the given external balances, e.g. the balances reported by an exchange.
+    /// The values of the positions of quanto instruments are denominated in
+    /// their index assets (see [`Instrument::quanto`]), so they are attributed to them.
+    ///
+    /// Return the discrepancies whose `|delta|` is greater than `tol`, sorted by asset.
+    /// The assets missing on either side are treated as zero.
+    pub fn reconcile(&self, balances: &HashMap<Asset, T>, tol: &T) -> Vec<Discrepancy<T>> {
+        let mut cash: HashMap<&Asset, T> = HashMap::default();
+        for (asset, sv) in self.values.iter() {
+            *cash.entry(asset).or_insert_with(T::zero) += &sv.value;
+            for p in sv.positions.values() {
+                let asset = p.instrument.quanto_index().unwrap_or(asset);
+                *cash.entry(asset).or_insert_with(T::zero) += &p.naive.value;
             }
-            if idx == len - 1 {
-                writeln!(f, "{LAST}{inst} => {p}")?;
-            } else {
-                writeln!(f, "{MIDDLE}{inst} => {p}")?;
+        }
+        let assets = cash
+            .keys()
+            .copied()
+            .chain(balances.keys())
+            .collect::<HashSet<_>>();
+        let mut discrepancies = Vec::new();
+        for asset in assets {
+            let actual = cash.get(asset).cloned().unwrap_or_else(T::zero);
+            let expected = balances.get(asset).cloned().unwrap_or_else(T::zero);
+            let delta = actual.clone() - expected.clone();
+            if delta.abs() > *tol {
+                discrepancies.push(Discrepancy {
+                    asset: asset.clone(),
+                    expected,
+                    actual,
+                    delta,
+                });
             }
         }
-        Ok(())
+        discrepancies.sort_by(|lhs, rhs| lhs.asset.cmp(&rhs.asset));
+        discrepancies
     }
-}
 
-impl<T> fmt::Display for Positions<T>
-where
-    T: PositionNum + fmt::Display,
-{
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    /// Check the self-consistency of the book, e.g. for the double-entry ledgers
+    /// built on top of it, which is intended for debugging and tests.
+    ///
+    /// - Every position must be filed under its `quote` asset and its own symbol.
+    /// - Every position must pass [`NaivePosition::debug_invariants`],
+    ///   e.g. a non-zero position with a non-positive price is corrupted
+    ///   even though it can still be evaluated.
+    /// - The equity evaluated by [`Expr::eval`] must be within `tolerance` of the sum
+    ///   of the contributions of the individual legs (the values and the positions),
+    ///   each converted to the `root` asset with the `asset-root` spot price,
+    ///   where the `tolerance` absorbs the rounding of the different summation orders.
+    ///   A NaN on either side is always a mismatch.
+    ///
+    /// On both sides, a missing spot is replaced with the reciprocal of its reversed spot,
+    /// e.g. the book can be priced with `USDT-BTC` instead of `BTC-USDT` (see [`cross_rate`]).
+    pub fn assert_balanced(
+        &self,
+        root: &Asset,
+        prices: &HashMap<Symbol, T>,
+        tolerance: &T,
+    ) -> Result<(), ImbalanceError<T>> {
         for (asset, sv) in self.values.iter() {
-            writeln!(f, "{asset} => {} {asset}", sv.value)?;
-            write!(f, "{sv}")?;
+            for (symbol, p) in sv.positions.iter() {
+                if p.instrument.quote() != asset || p.instrument.as_symbol() != symbol {
+                    return Err(ImbalanceError::Misfiled(symbol.clone()));
+                }
+                if let Err(reason) = p.naive.debug_invariants() {
+                    return Err(ImbalanceError::Invalid {
+                        symbol: symbol.clone(),
+                        reason,
+                    });
+                }
+            }
+        }
+        let prices = WithReversedSpots(prices);
+        let rate = |asset: &Asset| prices.spot_price(asset, root);
+        let mut legs = T::zero();
+        for (asset, sv) in self.values.iter() {
+            let mut value = rate(asset).ok_or(ImbalanceError::MissingPrices)?;
+            value *= &sv.value;
+            legs += value;
+            for p in sv.positions.values() {
+                let mut value = rate(p.instrument.quote()).ok_or(ImbalanceError::MissingPrices)?;
+                value *= p
+                    .closed_with_provider(&prices)
+                    .ok_or(ImbalanceError::MissingPrices)?;
+                legs += value;
+            }
+        }
+        let equity = self
+            .as_expr()
+            .eval(root, &prices)
+            .ok_or(ImbalanceError::MissingPrices)?;
+        let diff = (equity.clone() - legs.clone()).abs();
+        let within = matches!(
+            diff.partial_cmp(tolerance),
+            Some(core::cmp::Ordering::Less | core::cmp::Ordering::Equal)
+        );
+        if !within {
+            return Err(ImbalanceError::Mismatch { equity, legs });
         }
         Ok(())
     }
-}
-
-/// Positions Expression.
-#[derive(Debug)]
-pub struct Expr<'a, T>(&'a Positions<T>);
 
-impl<'a, T> Clone for Expr<'a, T> {
-    fn clone(&self) -> Self {
-        Self(self.0)
+    /// Evaluate the positions with the given timestamped prices,
+    /// requiring all the prices used to be not older than `max_age`.
+    ///
+    /// Return [`StaleError::Stale`] with all the stale symbols,
+    /// or [`StaleError::Missing`] if there are missing prices.
+    #[cfg(feature = "std")]
+    pub fn eval_fresh(
+        &self,
+        root: &Asset,
+        prices: &HashMap<Symbol, (T, std::time::Instant)>,
+        max_age: std::time::Duration,
+    ) -> Result<T, StaleError> {
+        let now = std::time::Instant::now();
+        let mut seen = HashSet::new();
+        let mut fresh = HashMap::default();
+        let mut stale = Vec::new();
+        for inst in self.as_expr().instruments(root) {
+            let symbol = inst.as_symbol();
+            if !seen.insert(symbol.clone()) {
+                continue;
+            }
+            let Some((price, ts)) = prices.get(symbol) else {
+                return Err(StaleError::Missing(symbol.clone()));
+            };
+            if now.saturating_duration_since(*ts) > max_age {
+                stale.push(symbol.clone());
+            } else {
+                fresh.insert(symbol.clone(), price.clone());
+            }
+        }
+        if !stale.is_empty() {
+            return Err(StaleError::Stale(stale));
+        }
+        Ok(self
+            .as_expr()
+            .eval(root, &fresh)
+            .expect("all the required prices are checked"))
     }
-}
 
-impl<'a, T> Copy for Expr<'a, T> {}
+    /// Charge a fee in the given asset, which is deducted from the value of the asset
+    /// and recorded in the fee ledger (see [`Positions::fees`]).
+    pub fn charge_fee(&mut self, fee: T, asset: &Asset) -> &mut Self {
+        *self.fees.entry(asset.clone()).or_insert_with(T::zero) += &fee;
+        let value = -fee;
+        self.record(asset, &value, LedgerReason::Fee);
+        self.add_value(value, asset)
+    }
 
-impl<'a, T> Expr<'a, T> {
-    /// Create a [`Expr`] from a [`Positions`].
-    #[inline]
-    pub fn new(positions: &'a Positions<T>) -> Self {
-        Self(positions)
+    /// Accrue the borrow interest of a short in `asset` (a negative value of it),
+    /// booking `value * rate` as a (negative) value of `asset`, and return it.
+    /// Nothing is booked and zero is returned if the value is not negative.
+    pub fn accrue_borrow(&mut self, asset: &Asset, rate: &T) -> T {
+        let interest = match self.values.get(asset) {
+            Some(sv) if sv.value.is_negative() => sv.value.clone() * rate.clone(),
+            _ => return T::zero(),
+        };
+        self.record(asset, &interest, LedgerReason::Borrow);
+        self.add_value(interest.clone(), asset);
+        interest
     }
-}
 
-impl<'a, T> Deref for Expr<'a, T> {
-    type Target = Positions<T>;
+    /// Start recording the cash events into the ledger, see [`Positions::ledger`].
+    /// Nothing is recorded (and paid) until this is called.
+    pub fn start_recording(&mut self) -> &mut Self {
+        self.ledger.get_or_insert_with(Vec::new);
+        self
+    }
 
-    fn deref(&self) -> &Self::Target {
-        self.0
+    /// Stop recording and take the recorded entries.
+    pub fn stop_recording(&mut self) -> Vec<LedgerEntry<T>> {
+        self.ledger.take().unwrap_or_default()
     }
-}
 
-impl<'a, T: PositionNum> Expr<'a, T> {
-    /// Get the reference instruments.
-    pub fn instruments<'b>(&'b self, root: &'b Asset) -> impl Iterator<Item = Instrument> + 'b {
-        self.0.values.iter().flat_map(move |(asset, sv)| {
-            let strong = if asset == root {
-                None
-            } else {
-                Some(Instrument::spot(asset, root))
-            };
-            sv.positions
-                .values()
-                .map(|p| p.instrument.clone())
-                .chain(strong)
-        })
+    /// Whether the cash events are being recorded.
+    pub fn is_recording(&self) -> bool {
+        self.ledger.is_some()
     }
 
-    /// Evaluate the expression with the given prices.
-    /// Return [`None`] if there are missing prices.
-    pub fn eval(&self, root: &Asset, prices: &HashMap<Symbol, T>) -> Option<T> {
-        self.eval_with(root, |p| {
-            Some(p.closed(prices.get(p.instrument().as_symbol())?))
-        })
+    /// Get the ledger of the cash events recorded in order since
+    /// [`Positions::start_recording`], which are the values inserted
+    /// by [`Positions::insert_value`], the fees charged by [`Positions::charge_fee`],
+    /// the interest accrued by [`Positions::accrue_borrow`],
+    /// the values realized by closing positions with [`Positions::insert_position`]
+    /// and the values moved by [`Positions::concentrate`].
+    ///
+    /// The ledger is not compared, merged or serialized with the table.
+    pub fn ledger(&self) -> &[LedgerEntry<T>] {
+        self.ledger.as_deref().unwrap_or_default()
     }
 
-    /// Evaluate the expression with the value returned by the given function.
-    /// Return [`None`] if there is something wrong.
-    pub fn eval_with<F>(&self, root: &Asset, mut eval: F) -> Option<T>
-    where
-        F: FnMut(&Position<T>) -> Option<T>,
-    {
-        self.0
-            .values
+    /// Clear the recorded entries and keep recording.
+    pub fn clear_ledger(&mut self) {
+        if let Some(ledger) = self.ledger.as_mut() {
+            ledger.clear();
+        }
+    }
+
+    fn record(&mut self, asset: &Asset, amount: &T, reason: LedgerReason) {
+        if let Some(ledger) = self.ledger.as_mut() {
+            if !amount.is_zero() {
+                ledger.push(LedgerEntry {
+                    asset: asset.clone(),
+                    amount: amount.clone(),
+                    reason,
+                });
+            }
+        }
+    }
+
+    /// Get the sum of the fees charged, converted to the `root` asset
+    /// with the `asset-root` spots or the reciprocals of the `root-asset` spots
+    /// (see [`cross_rate`]).
+    /// Return [`None`] if there are missing prices.
+    pub fn total_fees(&self, root: &Asset, prices: &HashMap<Symbol, T>) -> Option<T> {
+        self.fees
             .iter()
-            .map(move |(asset, sv)| {
-                let weak = sv
+            .map(|(asset, fee)| {
+                let mut fee = fee.clone();
+                fee *= cross_rate(prices, asset, root, root)?;
+                Some(fee)
+            })
+            .try_fold(T::zero(), |acc, x| Some(acc + x?))
+    }
+
+    /// Evaluate the equity before and after the fees, i.e. `(gross, net)`,
+    /// where `net` is the result of [`Expr::eval`] and `gross = net + total_fees`.
+    /// Return [`None`] if there are missing prices.
+    pub fn equity_with_fees(&self, root: &Asset, prices: &HashMap<Symbol, T>) -> Option<(T, T)> {
+        let net = self.as_expr().eval(root, prices)?;
+        let gross = net.clone() + self.total_fees(root, prices)?;
+        Some((gross, net))
+    }
+
+    /// Split the equity into the cash part and the positions part,
+    /// both converted to the `root` asset with the given prices.
+    ///
+    /// The cash part is the sum of the values of the assets,
+    /// and the positions part is the sum of the values of closing all positions.
+    /// Return [`None`] if there are missing prices.
+    pub fn snapshot_equity_components(
+        &self,
+        root: &Asset,
+        prices: &HashMap<Symbol, T>,
+    ) -> Option<(T, T)> {
+        self.values.iter().try_fold(
+            (T::zero(), T::zero()),
+            |(mut cash, mut positions), (asset, sv)| {
+                let mut value = sv.value.clone();
+                let mut closed = sv
                     .positions
                     .values()
-                    .map(&mut eval)
-                    .try_fold(T::zero(), |acc, x| Some(acc + x?));
-                let value = weak.map(|v| v + sv.value.clone());
-                if asset == root {
-                    value
-                } else {
-                    let p = Instrument::spot(asset, root).position((T::zero(), value?));
-                    Some((eval)(&p)?)
+                    .map(|p| p.closed_with_provider(prices))
+                    .try_fold(T::zero(), |acc, x| Some(acc + x?))?;
+                if asset != root {
+                    let price = prices.get(&Symbol::spot(asset, root))?;
+                    value *= price;
+                    closed *= price;
                 }
-            })
-            .try_fold(T::zero(), |acc, x| Some(acc + x?))
+                cash += value;
+                positions += closed;
+                Some((cash, positions))
+            },
+        )
     }
 }
 
-impl<'a, T: PositionNum + fmt::Display> fmt::Display for Expr<'a, T> {
+/// The compact serde representation of [`Positions`].
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+struct CompactRef<'a, T> {
+    values: Vec<(&'a Asset, &'a T)>,
+    positions: Vec<&'a Position<T>>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    fees: &'a HashMap<Asset, T>,
+}
+
+/// The serde representations of [`Positions`] that can be deserialized.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(untagged, bound(deserialize = "T: Deserialize<'de>"))]
+enum Repr<T> {
+    Compact {
+        values: Vec<(Asset, T)>,
+        positions: Vec<Position<T>>,
+        #[serde(default)]
+        fees: HashMap<Asset, T>,
+    },
+    Nested {
+        values: HashMap<Asset, SingleValue<T>>,
+        #[serde(default)]
+        fees: HashMap<Asset, T>,
+    },
+}
+
+#[cfg(feature = "serde")]
+impl<T> Serialize for Positions<T>
+where
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        CompactRef {
+            values: self
+                .values
+                .iter()
+                .map(|(asset, sv)| (asset, &sv.value))
+                .collect(),
+            positions: self
+                .values
+                .values()
+                .flat_map(|sv| sv.positions.values())
+                .collect(),
+            fees: &self.fees,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T> Deserialize<'de> for Positions<T>
+where
+    T: PositionNum + Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        match Repr::<T>::deserialize(deserializer)? {
+            Repr::Compact {
+                values,
+                positions,
+                fees,
+            } => {
+                let mut table = Positions {
+                    values: HashMap::with_capacity(values.len()),
+                    fees,
+                    ledger: None,
+                };
+                for (asset, value) in values {
+                    table.values.entry(asset).or_default().value += value;
+                }
+                for position in positions {
+                    table.insert_position(position);
+                }
+                Ok(table)
+            }
+            Repr::Nested { values, fees } => Ok(Positions {
+                values,
+                fees,
+                ledger: None,
+            }),
+        }
+    }
+}
+
+/// A line of the JSON-lines format of [`Positions`].
+#[cfg(feature = "json")]
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum LineRef<'a, T> {
+    Value { asset: &'a Asset, value: &'a T },
+    Position { position: &'a Position<T> },
+    Fee { asset: &'a Asset, fee: &'a T },
+}
+
+/// A line of the JSON-lines format of [`Positions`].
+#[cfg(feature = "json")]
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Line<T> {
+    Value { asset: Asset, value: T },
+    Position { position: Position<T> },
+    Fee { asset: Asset, fee: T },
+}
+
+#[cfg(feature = "json")]
+impl<T> Positions<T>
+where
+    T: PositionNum + Serialize + for<'de> Deserialize<'de>,
+{
+    /// Write the positions in the JSON-lines format,
+    /// one line for the value of each asset, one line for each position
+    /// and one line for the fee of each asset.
+    pub fn to_ndjson(&self, w: &mut impl std::io::Write) -> serde_json::Result<()> {
+        for (asset, sv) in self.values.iter() {
+            let value = LineRef::Value {
+                asset,
+                value: &sv.value,
+            };
+            let positions = sv
+                .positions
+                .values()
+                .map(|position| LineRef::Position { position });
+            for line in core::iter::once(value).chain(positions) {
+                serde_json::to_writer(&mut *w, &line)?;
+                w.write_all(b"\n").map_err(serde_json::Error::io)?;
+            }
+        }
+        for (asset, fee) in self.fees.iter() {
+            serde_json::to_writer(&mut *w, &LineRef::Fee { asset, fee })?;
+            w.write_all(b"\n").map_err(serde_json::Error::io)?;
+        }
+        Ok(())
+    }
+
+    /// Read the positions from the JSON-lines format written by [`Positions::to_ndjson`].
+    /// Empty lines are skipped.
+    pub fn from_ndjson(r: impl std::io::BufRead) -> serde_json::Result<Self> {
+        let mut positions = Self::default();
+        for line in r.lines() {
+            let line = line.map_err(serde_json::Error::io)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(&line)? {
+                Line::Value { asset, value } => {
+                    positions.insert_value(value, &asset);
+                }
+                Line::Position { position } => {
+                    positions.insert_position(position);
+                }
+                Line::Fee { asset, fee } => {
+                    *positions.fees.entry(asset).or_insert_with(T::zero) += fee;
+                }
+            }
+        }
+        Ok(positions)
+    }
+}
+
+/// A record of the binary checkpoint format of [`Positions`].
+///
+/// The positions are stored in their raw parts, since the flattened
+/// and skipped fields of the serde representation of [`Position`]
+/// are not supported by `bincode`.
+#[cfg(feature = "bincode")]
+#[derive(Serialize, Deserialize)]
+#[serde(bound(deserialize = "T: Deserialize<'de>"))]
+enum Record<T> {
+    Value {
+        asset: Asset,
+        value: T,
+    },
+    Position {
+        instrument: crate::instrument::InstrumentRecord,
+        naive: NaivePosition<T>,
+    },
+    Fee {
+        asset: Asset,
+        fee: T,
+    },
+}
+
+#[cfg(feature = "bincode")]
+impl<T> Positions<T>
+where
+    T: PositionNum + Serialize + for<'de> Deserialize<'de>,
+{
+    /// Write a checkpoint of the positions in the compact binary format of `bincode`.
+    pub fn to_bincode(&self, w: impl std::io::Write) -> bincode::Result<()> {
+        let mut records = Vec::new();
+        for (asset, sv) in self.values.iter() {
+            records.push(Record::Value {
+                asset: asset.clone(),
+                value: sv.value.clone(),
+            });
+            records.extend(sv.positions.values().map(|p| Record::Position {
+                instrument: (&p.instrument).into(),
+                naive: p.naive.clone(),
+            }));
+        }
+        records.extend(self.fees.iter().map(|(asset, fee)| Record::Fee {
+            asset: asset.clone(),
+            fee: fee.clone(),
+        }));
+        bincode::serialize_into(w, &records)
+    }
+
+    /// Read the positions from a checkpoint written by [`Positions::to_bincode`].
+    pub fn from_bincode(r: impl std::io::Read) -> bincode::Result<Self> {
+        let records: Vec<Record<T>> = bincode::deserialize_from(r)?;
+        let mut positions = Self::default();
+        for record in records {
+            match record {
+                Record::Value { asset, value } => {
+                    positions.insert_value(value, &asset);
+                }
+                Record::Position { instrument, naive } => {
+                    positions.insert_position(Position::new(instrument.into(), naive));
+                }
+                Record::Fee { asset, fee } => {
+                    *positions.fees.entry(asset).or_insert_with(T::zero) += fee;
+                }
+            }
+        }
+        Ok(positions)
+    }
+}
+
+/// Parse positions error.
+#[derive(Debug)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+pub enum ParsePositionsError {
+    /// Invalid segment.
+    #[cfg_attr(feature = "thiserror", error("invalid segment `{0}`"))]
+    InvalidSegment(String),
+    /// Invalid number.
+    #[cfg_attr(feature = "thiserror", error("invalid number `{0}`"))]
+    InvalidNumber(String),
+    /// Asset errors.
+    #[cfg_attr(feature = "thiserror", error("parse asset error: {0}"))]
+    Asset(ParseAssetError),
+    /// Symbol errors.
+    #[cfg_attr(feature = "thiserror", error("parse symbol error: {0}"))]
+    Symbol(ParseSymbolError),
+}
+
+#[cfg(not(feature = "thiserror"))]
+impl fmt::Display for ParsePositionsError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.0.is_empty() {
-            return write!(f, "0");
+        match self {
+            Self::InvalidSegment(s) => write!(f, "invalid segment `{s}`"),
+            Self::InvalidNumber(s) => write!(f, "invalid number `{s}`"),
+            Self::Asset(err) => write!(f, "parse asset error: {err}"),
+            Self::Symbol(err) => write!(f, "parse symbol error: {err}"),
         }
-        for (idx, (asset, sv)) in self.0.values.iter().enumerate() {
-            let mut value = sv.value().clone();
-            let first_sv = idx == 0;
-            let no_position = sv.is_empty();
-            for (idx, p) in sv.positions.values().enumerate() {
-                if !(first_sv && idx == 0) {
-                    write!(f, " + ")?;
+    }
+}
+
+impl From<ParseAssetError> for ParsePositionsError {
+    fn from(err: ParseAssetError) -> Self {
+        Self::Asset(err)
+    }
+}
+
+impl From<ParseSymbolError> for ParsePositionsError {
+    fn from(err: ParseSymbolError) -> Self {
+        Self::Symbol(err)
+    }
+}
+
+fn parse_num<T: FromStr>(s: &str) -> Result<T, ParsePositionsError> {
+    s.trim()
+        .parse()
+        .map_err(|_| ParsePositionsError::InvalidNumber(s.to_string()))
+}
+
+fn parse_instrument(s: &str) -> Result<Instrument, ParsePositionsError> {
+    let invalid = || ParsePositionsError::InvalidSegment(s.to_string());
+    let (s, reversed) = match s.strip_suffix('*') {
+        Some(s) => (s, true),
+        None => (s, false),
+    };
+    let (symbol, pair) = match s.strip_suffix(')') {
+        Some(s) => {
+            let (symbol, pair) = s.split_once('(').ok_or_else(invalid)?;
+            let (base, quote) = pair.split_once('/').ok_or_else(invalid)?;
+            (
+                symbol,
+                Some((Asset::from_str(base)?, Asset::from_str(quote)?)),
+            )
+        }
+        None => (s, None),
+    };
+    let symbol = Symbol::from_str(symbol)?;
+    let (base, quote) = match (pair, symbol.as_spot(), symbol.derivative_symbol()) {
+        (Some(pair), _, _) => pair,
+        (None, Some((base, quote)), _) => (base.clone(), quote.clone()),
+        (None, None, Some(derivative)) => {
+            let mut parts = derivative.split(Asset::SEP);
+            let base = parts.next().ok_or_else(invalid)?;
+            let quote = parts.next().ok_or_else(invalid)?;
+            (Asset::from_str(base)?, Asset::from_str(quote)?)
+        }
+        (None, None, None) => return Err(invalid()),
+    };
+    Ok(Instrument::try_with_symbol(symbol, &base, &quote)?.prefer_reversed(reversed))
+}
+
+impl<T> FromStr for Positions<T>
+where
+    T: PositionNum + FromStr,
+{
+    type Err = ParsePositionsError;
+
+    /// Parse positions from a compact format of `;`-separated segments,
+    /// e.g. `BTC:1;USDT:-16000;SWAP:BTC-USDT-SWAP@16001:-1.5`.
+    ///
+    /// - `asset:value` is the value of an asset.
+    /// - `symbol@price:size` or `symbol@price:size:value` is a position.
+    ///   The base and quote assets of a derivative are inferred from the first two
+    ///   `-`-separated parts of its symbol, or can be given explicitly as
+    ///   `SWAP:BTC-USD-SWAP(USD/BTC)`. A trailing `*` marks the instrument
+    ///   as reversed-prefering, and then the price and size are in the reversed-form.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut positions = Self::default();
+        for segment in s.split(';').map(str::trim).filter(|s| !s.is_empty()) {
+            if let Some((inst, numbers)) = segment.split_once('@') {
+                let instrument = parse_instrument(inst.trim())?;
+                let mut numbers = numbers.split(':');
+                let (Some(price), Some(size)) = (numbers.next(), numbers.next()) else {
+                    return Err(ParsePositionsError::InvalidSegment(segment.to_string()));
+                };
+                let (price, size) = (parse_num::<T>(price)?, parse_num::<T>(size)?);
+                let value = numbers.next().map(parse_num::<T>).transpose()?;
+                if numbers.next().is_some() {
+                    return Err(ParsePositionsError::InvalidSegment(segment.to_string()));
                 }
-                value += p.value();
-                let naive = p.as_naive();
-                super::tree::write_position(f, &naive.price, &naive.size, p.instrument())?;
+                let value = value.unwrap_or_else(T::zero);
+                let position = if instrument.is_prefer_reversed() {
+                    if price.is_zero() {
+                        return Err(ParsePositionsError::InvalidNumber(segment.to_string()));
+                    }
+                    Position::new(instrument, Reversed((price, size, value)))
+                } else {
+                    Position::new(instrument, (price, size, value))
+                };
+                positions.insert_position(position);
+            } else {
+                let (asset, value) = segment
+                    .split_once(':')
+                    .ok_or_else(|| ParsePositionsError::InvalidSegment(segment.to_string()))?;
+                positions.insert_value(parse_num(value)?, &Asset::from_str(asset.trim())?);
             }
-            if first_sv && no_position {
-                write!(f, "{} {asset}", value)?;
+        }
+        Ok(positions)
+    }
+}
+
+impl<T> IntoIterator for Positions<T> {
+    type Item = (Asset, SingleValue<T>);
+
+    type IntoIter = <HashMap<Asset, SingleValue<T>> as IntoIterator>::IntoIter;
+
+    #[inline]
+    fn into_iter(self) -> Self::IntoIter {
+        self.values.into_iter()
+    }
+}
+
+impl<T> PartialEq for Positions<T>
+where
+    T: PositionNum,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.values == other.values && self.fees == other.fees
+    }
+}
+
+impl<T> Eq for Positions<T> where T: PositionNum {}
+
+impl<T> AddAssign<&Self> for Positions<T>
+where
+    T: PositionNum,
+{
+    fn add_assign(&mut self, rhs: &Self) {
+        for (asset, rhs) in rhs.values.iter() {
+            if let Some(lhs) = self.values.get_mut(asset) {
+                *lhs += rhs;
             } else {
-                let sign = if value.is_negative() { " - " } else { " + " };
-                write!(f, "{sign}{} {asset}", value.abs())?;
+                self.values.insert(asset.clone(), rhs.clone());
             }
         }
-        Ok(())
+        for (asset, fee) in rhs.fees.iter() {
+            *self.fees.entry(asset.clone()).or_insert_with(T::zero) += fee;
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{asset::Asset, Reversed};
-    use fraction::{BigInt, GenericDecimal, Zero};
+impl<T> AddAssign for Positions<T>
+where
+    T: PositionNum,
+{
+    fn add_assign(&mut self, rhs: Self) {
+        *self += &rhs;
+    }
+}
+
+impl<T> Add for Positions<T>
+where
+    T: PositionNum,
+{
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self += rhs;
+        self
+    }
+}
+
+impl<T> AddAssign<Position<T>> for Positions<T>
+where
+    T: PositionNum,
+{
+    fn add_assign(&mut self, rhs: Position<T>) {
+        self.insert_position(rhs);
+    }
+}
+
+impl<'a, T> AddAssign<(T, &'a Asset)> for Positions<T>
+where
+    T: PositionNum,
+{
+    fn add_assign(&mut self, (value, asset): (T, &'a Asset)) {
+        self.insert_value(value, asset);
+    }
+}
+
+impl<'a, T> Extend<(T, &'a Asset)> for Positions<T>
+where
+    T: PositionNum,
+{
+    fn extend<I: IntoIterator<Item = (T, &'a Asset)>>(&mut self, iter: I) {
+        for (value, asset) in iter {
+            self.insert_value(value, asset);
+        }
+    }
+}
+
+impl<'a, T> AddAssign<(T, T, &'a Instrument)> for Positions<T>
+where
+    T: PositionNum,
+{
+    fn add_assign(&mut self, (price, size, instrument): (T, T, &'a Instrument)) {
+        self.insert_position(Position::new(instrument.clone(), (price, size)));
+    }
+}
+
+impl<'a, T> AddAssign<(T, T, T, &'a Instrument)> for Positions<T>
+where
+    T: PositionNum,
+{
+    fn add_assign(&mut self, (price, size, value, instrument): (T, T, T, &'a Instrument)) {
+        self.insert_position(Position::new(instrument.clone(), (price, size, value)));
+    }
+}
+
+impl<'a, T> AddAssign<Reversed<(T, T, &'a Instrument)>> for Positions<T>
+where
+    T: PositionNum,
+{
+    fn add_assign(
+        &mut self,
+        Reversed((price, size, instrument)): Reversed<(T, T, &'a Instrument)>,
+    ) {
+        self.insert_position(Position::new(instrument.clone(), Reversed((price, size))));
+    }
+}
+
+impl<'a, T> AddAssign<Reversed<(T, T, T, &'a Instrument)>> for Positions<T>
+where
+    T: PositionNum,
+{
+    fn add_assign(
+        &mut self,
+        Reversed((price, size, value, instrument)): Reversed<(T, T, T, &'a Instrument)>,
+    ) {
+        self.insert_position(Position::new(
+            instrument.clone(),
+            Reversed((price, size, value)),
+        ));
+    }
+}
+
+impl<T> From<Position<T>> for Positions<T>
+where
+    T: PositionNum,
+{
+    fn from(p: Position<T>) -> Self {
+        let asset = p.instrument.quote().clone();
+        let inst = p.instrument.as_symbol().clone();
+        let sv = SingleValue {
+            value: T::zero(),
+            positions: HashMap::from([(inst, p)]),
+        };
+        Self {
+            values: HashMap::from([(asset, sv)]),
+            fees: HashMap::default(),
+            ledger: None,
+        }
+    }
+}
+
+/// Collapse a table holding a single instrument into its position,
+/// folding the value of the quote asset into the value of the position.
+/// The value of the quote asset cannot be folded into a quanto position,
+/// whose value is denominated in the index asset (see [`Instrument::quanto`]).
+/// The fee ledger is dropped, since the fees have been booked into the values.
+impl<T> TryFrom<Positions<T>> for Position<T>
+where
+    T: PositionNum,
+{
+    type Error = IntoPositionError;
+
+    fn try_from(positions: Positions<T>) -> Result<Self, Self::Error> {
+        let mut found = None;
+        for p in positions
+            .values
+            .values()
+            .flat_map(|sv| sv.positions.values())
+        {
+            if p.is_zero() {
+                continue;
+            }
+            if found.is_some() {
+                return Err(IntoPositionError::Ambiguous);
+            }
+            found = Some(p);
+        }
+        let mut position = found.ok_or(IntoPositionError::Empty)?.clone();
+        for (asset, sv) in positions.values.iter() {
+            if sv.value.is_zero() {
+                continue;
+            }
+            // The value of a quanto position is denominated in its index asset.
+            if asset != position.instrument.quote() || position.instrument.is_quanto() {
+                return Err(IntoPositionError::ForeignValue(asset.clone()));
+            }
+            position.naive.value += &sv.value;
+        }
+        Ok(position)
+    }
+}
+
+impl<T> fmt::Display for SingleValue<T>
+where
+    T: fmt::Display + PositionNum,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MIDDLE: &str = "├ ";
+        const LAST: &str = "└ ";
+        let len = self.positions.len();
+        for (idx, (inst, p)) in self.positions.iter().enumerate() {
+            if p.is_zero() {
+                continue;
+            }
+            if idx == len - 1 {
+                writeln!(f, "{LAST}{inst} => {p}")?;
+            } else {
+                writeln!(f, "{MIDDLE}{inst} => {p}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T> fmt::Display for Positions<T>
+where
+    T: PositionNum + fmt::Display,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (asset, sv) in self.values.iter() {
+            writeln!(f, "{asset} => {} {asset}", sv.value)?;
+            write!(f, "{sv}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare all the fields of the instruments, while the `PartialEq` only compares the symbols.
+fn is_identical(lhs: &Instrument, rhs: &Instrument) -> bool {
+    lhs.as_symbol() == rhs.as_symbol()
+        && lhs.base() == rhs.base()
+        && lhs.quote() == rhs.quote()
+        && lhs.quanto_index() == rhs.quanto_index()
+        && lhs.is_prefer_reversed() == rhs.is_prefer_reversed()
+}
+
+/// Error of converting the prices of positions, see [`Positions::convert_all`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+pub enum ConvertError {
+    /// The price of the symbol is missing.
+    #[cfg_attr(feature = "thiserror", error("missing price of {0}"))]
+    MissingPrice(Symbol),
+    /// The price of the reversed-prefering symbol is zero.
+    #[cfg_attr(feature = "thiserror", error("zero reversed-form price of {0}"))]
+    ZeroPrice(Symbol),
+}
+
+#[cfg(not(feature = "thiserror"))]
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingPrice(symbol) => write!(f, "missing price of {symbol}"),
+            Self::ZeroPrice(symbol) => write!(f, "zero reversed-form price of {symbol}"),
+        }
+    }
+}
+
+/// Error of merging positions of different instruments, see [`Position::try_merge`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+#[cfg_attr(
+    feature = "thiserror",
+    error("cannot merge positions of different instruments: {lhs:?} and {rhs:?}")
+)]
+pub struct MergeError {
+    /// The instrument of the merged-into position.
+    pub lhs: Box<Instrument>,
+    /// The instrument of the other position.
+    pub rhs: Box<Instrument>,
+}
+
+#[cfg(not(feature = "thiserror"))]
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cannot merge positions of different instruments: {:?} and {:?}",
+            self.lhs, self.rhs
+        )
+    }
+}
+
+/// Error of collapsing a [`Positions`] into a single [`Position`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+pub enum IntoPositionError {
+    /// There is no non-zero position.
+    #[cfg_attr(feature = "thiserror", error("no non-zero position"))]
+    Empty,
+    /// There are multiple non-zero positions.
+    #[cfg_attr(feature = "thiserror", error("multiple non-zero positions"))]
+    Ambiguous,
+    /// There is non-zero value that cannot be folded into the position,
+    /// i.e. of an asset other than the quote, or of the quote of a quanto position.
+    #[cfg_attr(
+        feature = "thiserror",
+        error("non-zero value of {0} besides the quote")
+    )]
+    ForeignValue(Asset),
+}
+
+#[cfg(not(feature = "thiserror"))]
+impl fmt::Display for IntoPositionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "no non-zero position"),
+            Self::Ambiguous => write!(f, "multiple non-zero positions"),
+            Self::ForeignValue(asset) => write!(f, "non-zero value of {asset} besides the quote"),
+        }
+    }
+}
+
+/// A cash event recorded in the ledger of [`Positions`], see [`Positions::ledger`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LedgerEntry<T> {
+    /// The asset.
+    pub asset: Asset,
+    /// The amount, which is negative for outflows.
+    pub amount: T,
+    /// The reason.
+    pub reason: LedgerReason,
+}
+
+/// The reason of a [`LedgerEntry`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LedgerReason {
+    /// A value is inserted.
+    Insert,
+    /// A fee is charged.
+    Fee,
+    /// A borrow interest is accrued.
+    Borrow,
+    /// A value is realized by closing the position of the symbol.
+    Realize(Symbol),
+    /// The value of the position of the symbol is moved to the cash.
+    Concentrate(Symbol),
+}
+
+/// Error of checking the self-consistency of a book, see [`Positions::assert_balanced`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+pub enum ImbalanceError<T> {
+    /// The position is not filed under its quote asset or its symbol.
+    #[cfg_attr(feature = "thiserror", error("misfiled position of {0}"))]
+    Misfiled(Symbol),
+    /// The position is in a broken state, see [`NaivePosition::debug_invariants`].
+    #[cfg_attr(feature = "thiserror", error("invalid position of {symbol}: {reason}"))]
+    Invalid {
+        /// The symbol of the position.
+        symbol: Symbol,
+        /// The broken invariant.
+        reason: &'static str,
+    },
+    /// There are missing prices.
+    #[cfg_attr(feature = "thiserror", error("missing prices"))]
+    MissingPrices,
+    /// The equity is not within the tolerance of the sum of the legs.
+    #[cfg_attr(
+        feature = "thiserror",
+        error("the equity {equity} does not equal the sum of the legs {legs}")
+    )]
+    Mismatch {
+        /// The equity.
+        equity: T,
+        /// The sum of the legs.
+        legs: T,
+    },
+}
+
+#[cfg(not(feature = "thiserror"))]
+impl<T: fmt::Display> fmt::Display for ImbalanceError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Misfiled(symbol) => write!(f, "misfiled position of {symbol}"),
+            Self::Invalid { symbol, reason } => {
+                write!(f, "invalid position of {symbol}: {reason}")
+            }
+            Self::MissingPrices => write!(f, "missing prices"),
+            Self::Mismatch { equity, legs } => write!(
+                f,
+                "the equity {equity} does not equal the sum of the legs {legs}"
+            ),
+        }
+    }
+}
+
+/// A mismatch between the cash of an asset and its external balance,
+/// see [`Positions::reconcile`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Discrepancy<T> {
+    /// The asset.
+    pub asset: Asset,
+    /// The external balance.
+    pub expected: T,
+    /// The cash computed from the positions.
+    pub actual: T,
+    /// `actual - expected`.
+    pub delta: T,
+}
+
+/// A symbol shared by different instruments, see [`Positions::merge_duplicate_symbols`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolConflict {
+    /// The shared symbol.
+    pub symbol: Symbol,
+    /// The different instruments with the symbol.
+    pub instruments: Vec<Instrument>,
+}
+
+/// Error of evaluating with timestamped prices, see [`Positions::eval_fresh`].
+#[cfg(feature = "std")]
+#[derive(Debug, thiserror::Error)]
+pub enum StaleError {
+    /// The price of the symbol is missing.
+    #[error("missing price of {0}")]
+    Missing(Symbol),
+    /// The prices of the symbols are older than the tolerance.
+    #[error(
+        "stale prices of {}",
+        .0.iter().map(|s| s.to_string()).collect::<Vec<_>>().join(", ")
+    )]
+    Stale(Vec<Symbol>),
+}
+
+/// The difference between two snapshots of [`Positions`], see [`diff_snapshots`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SnapshotDiff<T> {
+    values: HashMap<Asset, T>,
+    added: Vec<Position<T>>,
+    removed: Vec<Position<T>>,
+    changed: Vec<(Position<T>, Position<T>)>,
+}
+
+impl<T> SnapshotDiff<T> {
+    /// Get the changes of the values, keyed by asset.
+    /// Only the assets with non-zero changes are included.
+    pub fn values(&self) -> &HashMap<Asset, T> {
+        &self.values
+    }
+
+    /// Get the positions that only exist in the `after` snapshot.
+    pub fn added(&self) -> &[Position<T>] {
+        &self.added
+    }
+
+    /// Get the positions that only exist in the `before` snapshot.
+    pub fn removed(&self) -> &[Position<T>] {
+        &self.removed
+    }
+
+    /// Get the positions that are changed, as pairs of `(before, after)`.
+    pub fn changed(&self) -> &[(Position<T>, Position<T>)] {
+        &self.changed
+    }
+
+    /// Is there no difference.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+/// Tracker of the account equity over time, maintaining the high-water mark
+/// and the drawdowns, which are measured in the `root` asset.
+#[derive(Debug, Clone)]
+pub struct EquityTracker<T> {
+    root: Asset,
+    equity: Option<T>,
+    peak: Option<T>,
+    max_drawdown: T,
+}
+
+impl<T> EquityTracker<T>
+where
+    T: PositionNum,
+{
+    /// Create a tracker of the equity in the `root` asset.
+    pub fn new(root: &Asset) -> Self {
+        Self {
+            root: root.clone(),
+            equity: None,
+            peak: None,
+            max_drawdown: T::zero(),
+        }
+    }
+
+    /// Get the root asset.
+    pub fn root(&self) -> &Asset {
+        &self.root
+    }
+
+    /// Evaluate the equity of the `positions` with a snapshot of `prices`
+    /// and record it, see [`EquityTracker::record`].
+    /// Return [`None`] and leave the tracker unchanged if there are missing prices.
+    pub fn update(&mut self, positions: &Positions<T>, prices: &HashMap<Symbol, T>) -> Option<&T> {
+        let equity = positions.as_expr().eval(&self.root, prices)?;
+        self.record(equity);
+        self.equity.as_ref()
+    }
+
+    /// Record an equity.
+    pub fn record(&mut self, equity: T) {
+        match &mut self.peak {
+            Some(peak) if *peak >= equity => {}
+            peak => *peak = Some(equity.clone()),
+        }
+        self.equity = Some(equity);
+        let drawdown = self.drawdown();
+        if drawdown > self.max_drawdown {
+            self.max_drawdown = drawdown;
+        }
+    }
+
+    /// Get the last recorded equity.
+    pub fn equity(&self) -> Option<&T> {
+        self.equity.as_ref()
+    }
+
+    /// Get the peak equity (the high-water mark).
+    pub fn peak(&self) -> Option<&T> {
+        self.peak.as_ref()
+    }
+
+    /// Get the current drawdown, i.e. `peak - equity`,
+    /// which is zero if nothing has been recorded.
+    pub fn drawdown(&self) -> T {
+        match (&self.peak, &self.equity) {
+            (Some(peak), Some(equity)) => peak.clone() - equity.clone(),
+            _ => T::zero(),
+        }
+    }
+
+    /// Get the maximum drawdown so far.
+    pub fn max_drawdown(&self) -> &T {
+        &self.max_drawdown
+    }
+}
+
+/// Compute the difference between the `before` and `after` snapshots of [`Positions`].
+pub fn diff_snapshots<T>(before: &Positions<T>, after: &Positions<T>) -> SnapshotDiff<T>
+where
+    T: PositionNum,
+{
+    let mut diff = SnapshotDiff {
+        values: HashMap::default(),
+        added: Vec::new(),
+        removed: Vec::new(),
+        changed: Vec::new(),
+    };
+    let zero = SingleValue::default();
+    let assets = before
+        .values
+        .keys()
+        .chain(after.values.keys())
+        .collect::<HashSet<_>>();
+    for asset in assets {
+        let lhs = before.values.get(asset).unwrap_or(&zero);
+        let rhs = after.values.get(asset).unwrap_or(&zero);
+        let delta = rhs.value.clone() - lhs.value.clone();
+        if !delta.is_zero() {
+            diff.values.insert(asset.clone(), delta);
+        }
+        for (symbol, p) in lhs.positions.iter() {
+            match rhs.positions.get(symbol) {
+                Some(q) if p != q => diff.changed.push((p.clone(), q.clone())),
+                Some(_) => {}
+                None => diff.removed.push(p.clone()),
+            }
+        }
+        for (symbol, q) in rhs.positions.iter() {
+            if !lhs.positions.contains_key(symbol) {
+                diff.added.push(q.clone());
+            }
+        }
+    }
+    diff
+}
+
+/// Compute the cross rate of `base/quote` by triangulating through the spots
+/// `base-via` and `quote-via`, i.e. `(base/via) / (quote/via)`.
+///
+/// The inverse spots (`via-base` or `via-quote`) are used with their reciprocals
+/// if the direct ones are missing.
+/// Return [`None`] if the prices are missing or the rate of `quote/via` is zero.
+pub fn cross_rate<T>(
+    prices: &HashMap<Symbol, T>,
+    base: &Asset,
+    quote: &Asset,
+    via: &Asset,
+) -> Option<T>
+where
+    T: PositionNum,
+{
+    let rate = |asset: &Asset| -> Option<T> {
+        if asset == via {
+            return Some(T::one());
+        }
+        match prices.get(&Symbol::spot(asset, via)) {
+            Some(price) => Some(price.clone()),
+            None => prices.get(&Symbol::spot(via, asset))?.checked_reciprocal(),
+        }
+    };
+    let mut ans = rate(base)?;
+    let quote = rate(quote)?;
+    if quote.is_zero() {
+        return None;
+    }
+    ans /= quote;
+    Some(ans)
+}
+
+/// Annualize the return `ret` over a holding period of `days` days,
+/// without compounding, i.e. `ret * 365 / days`.
+/// # Panic
+/// Panic if `days` is zero and the division of `T` panics,
+/// or `365` cannot be represented by `T`.
+pub fn annualize<T>(ret: &T, days: &T) -> T
+where
+    T: PositionNum + FromPrimitive,
+{
+    let mut ans = T::from_u32(365).expect("365 cannot be represented");
+    ans *= ret;
+    ans /= days;
+    ans
+}
+
+/// Positions Expression.
+#[derive(Debug)]
+pub struct Expr<'a, T>(&'a Positions<T>);
+
+impl<'a, T> Clone for Expr<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for Expr<'a, T> {}
+
+impl<'a, T> Expr<'a, T> {
+    /// Create a [`Expr`] from a [`Positions`].
+    #[inline]
+    pub fn new(positions: &'a Positions<T>) -> Self {
+        Self(positions)
+    }
+}
+
+impl<'a, T> Deref for Expr<'a, T> {
+    type Target = Positions<T>;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+impl<'a, T: PositionNum> Expr<'a, T> {
+    /// Get the reference instruments.
+    /// The `index-quote` spots of quanto instruments are included.
+    pub fn instruments<'b>(&'b self, root: &'b Asset) -> impl Iterator<Item = Instrument> + 'b {
+        self.instruments_of(root)
+    }
+
+    fn instruments_of<'b>(self, root: &'b Asset) -> impl Iterator<Item = Instrument> + 'b
+    where
+        'a: 'b,
+    {
+        self.0.values.iter().flat_map(move |(asset, sv)| {
+            let strong = if asset == root {
+                None
+            } else {
+                Some(Instrument::spot(asset, root))
+            };
+            sv.positions
+                .values()
+                .flat_map(|p| {
+                    let rate = p
+                        .instrument
+                        .quanto_index()
+                        .map(|index| Instrument::spot(index, p.instrument.quote()));
+                    core::iter::once(p.instrument.clone()).chain(rate)
+                })
+                .chain(strong)
+        })
+    }
+
+    /// Evaluate the expression with the given prices,
+    /// e.g. a [`HashMap`] keyed by [`Symbol`] or [`Instrument`].
+    /// Return [`None`] if there are missing prices.
+    pub fn eval(&self, root: &Asset, prices: &impl PriceProvider<T>) -> Option<T> {
+        self.eval_with(root, |p| p.closed_with_provider(prices))
+    }
+
+    /// Evaluate the expression with the given prices keyed by [`Instrument`],
+    /// the same as [`Expr::eval`].
+    /// Return [`None`] if there are missing prices.
+    pub fn eval_by_instrument(&self, root: &Asset, prices: &HashMap<Instrument, T>) -> Option<T> {
+        self.eval(root, prices)
+    }
+
+    /// Evaluate the expression with a fallback chain of price sources,
+    /// e.g. a real-time source followed by the last-known prices.
+    /// Each price is taken from the first source that has it.
+    /// Return [`None`] if there are prices missing in all the sources.
+    pub fn eval_layered(&self, root: &Asset, sources: &[&HashMap<Symbol, T>]) -> Option<T> {
+        self.eval(root, &Layered(sources))
+    }
+
+    /// Evaluate the expression conservatively with the bid and ask prices,
+    /// i.e. the value of liquidating all the positions.
+    ///
+    /// Each position is closed at the bid if it is long and at the ask if it is short,
+    /// judged by the sign of [`Position::size`] (so the prices are in the reversed-form
+    /// for reversed-prefering instruments). The values of the assets are converted to
+    /// the root asset in the same way, with the bids for positive values and the asks for
+    /// negative ones. The rates of quanto instruments are taken from the same side.
+    /// Return [`None`] if there are missing prices.
+    pub fn eval_with_spread(
+        &self,
+        root: &Asset,
+        bids: &HashMap<Symbol, T>,
+        asks: &HashMap<Symbol, T>,
+    ) -> Option<T> {
+        self.eval_with(root, |p| {
+            if p.size().is_negative() {
+                p.closed_with_provider(asks)
+            } else {
+                p.closed_with_provider(bids)
+            }
+        })
+    }
+
+    /// Compile the expression with the given root asset,
+    /// so that it can be evaluated with different prices repeatedly
+    /// without rebuilding the instruments.
+    pub fn compile(&self, root: &Asset) -> CompiledExpr<T> {
+        let terms = self
+            .0
+            .values
+            .iter()
+            .map(|(asset, sv)| Term {
+                rate: if asset == root {
+                    None
+                } else {
+                    Some(Symbol::spot(asset, root))
+                },
+                value: sv.value.clone(),
+                positions: sv.positions.values().cloned().collect(),
+            })
+            .collect();
+        CompiledExpr {
+            instruments: self.instruments(root).collect(),
+            terms,
+        }
+    }
+
+    /// Evaluate the expression with the value returned by the given function.
+    /// Return [`None`] if there is something wrong.
+    pub fn eval_with<F>(&self, root: &Asset, mut eval: F) -> Option<T>
+    where
+        F: FnMut(&Position<T>) -> Option<T>,
+    {
+        self.0
+            .values
+            .iter()
+            .map(move |(asset, sv)| {
+                let weak = sv
+                    .positions
+                    .values()
+                    .map(&mut eval)
+                    .try_fold(T::zero(), |acc, x| Some(acc + x?));
+                let value = weak.map(|v| v + sv.value.clone());
+                if asset == root {
+                    value
+                } else {
+                    let p = Instrument::spot(asset, root).position((T::zero(), value?));
+                    Some((eval)(&p)?)
+                }
+            })
+            .try_fold(T::zero(), |acc, x| Some(acc + x?))
+    }
+}
+
+/// Owned positions expression, see [`Positions::into_expr`].
+///
+/// It has the same API as [`Expr`], but owns the [`Positions`]
+/// so that it can be returned from functions.
+#[derive(Debug, Clone)]
+pub struct OwnedExpr<T>(Positions<T>);
+
+impl<T> OwnedExpr<T> {
+    /// Get the [`Positions`] back.
+    #[inline]
+    pub fn into_inner(self) -> Positions<T> {
+        self.0
+    }
+}
+
+impl<T> Deref for OwnedExpr<T> {
+    type Target = Positions<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T: PositionNum> OwnedExpr<T> {
+    /// Borrow as an [`Expr`].
+    #[inline]
+    pub fn as_expr(&self) -> Expr<'_, T> {
+        Expr(&self.0)
+    }
+
+    /// Get the reference instruments, see [`Expr::instruments`].
+    pub fn instruments<'b>(&'b self, root: &'b Asset) -> impl Iterator<Item = Instrument> + 'b {
+        self.as_expr().instruments_of(root)
+    }
+
+    /// Evaluate the expression with the given prices, see [`Expr::eval`].
+    pub fn eval(&self, root: &Asset, prices: &impl PriceProvider<T>) -> Option<T> {
+        self.as_expr().eval(root, prices)
+    }
+
+    /// Evaluate the expression with the given prices keyed by [`Instrument`],
+    /// see [`Expr::eval_by_instrument`].
+    pub fn eval_by_instrument(&self, root: &Asset, prices: &HashMap<Instrument, T>) -> Option<T> {
+        self.as_expr().eval_by_instrument(root, prices)
+    }
+
+    /// Evaluate the expression with a fallback chain of price sources,
+    /// see [`Expr::eval_layered`].
+    pub fn eval_layered(&self, root: &Asset, sources: &[&HashMap<Symbol, T>]) -> Option<T> {
+        self.as_expr().eval_layered(root, sources)
+    }
+
+    /// Compile the expression with the given root asset, see [`Expr::compile`].
+    pub fn compile(&self, root: &Asset) -> CompiledExpr<T> {
+        self.as_expr().compile(root)
+    }
+
+    /// Evaluate the expression with the value returned by the given function,
+    /// see [`Expr::eval_with`].
+    pub fn eval_with<F>(&self, root: &Asset, eval: F) -> Option<T>
+    where
+        F: FnMut(&Position<T>) -> Option<T>,
+    {
+        self.as_expr().eval_with(root, eval)
+    }
+}
+
+impl<T: PositionNum + fmt::Display> fmt::Display for OwnedExpr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_expr().fmt(f)
+    }
+}
+
+/// Compiled positions expression with a fixed root asset.
+#[derive(Debug, Clone)]
+pub struct CompiledExpr<T> {
+    instruments: Vec<Instrument>,
+    terms: Vec<Term<T>>,
+}
+
+/// The positions and value of an asset,
+/// with the symbol of the price to convert them to the root asset.
+#[derive(Debug, Clone)]
+struct Term<T> {
+    rate: Option<Symbol>,
+    value: T,
+    positions: Vec<Position<T>>,
+}
+
+impl<T: PositionNum> CompiledExpr<T> {
+    /// Get the reference instruments.
+    pub fn instruments(&self) -> &[Instrument] {
+        &self.instruments
+    }
+
+    /// Evaluate the expression with the given prices.
+    /// Return [`None`] if there are missing prices.
+    pub fn eval(&self, prices: &HashMap<Symbol, T>) -> Option<T> {
+        self.terms
+            .iter()
+            .map(|term| {
+                let mut value = term
+                    .positions
+                    .iter()
+                    .map(|p| p.closed_with_provider(prices))
+                    .try_fold(term.value.clone(), |acc, x| Some(acc + x?))?;
+                if let Some(rate) = &term.rate {
+                    value *= prices.get(rate)?;
+                }
+                Some(value)
+            })
+            .try_fold(T::zero(), |acc, x| Some(acc + x?))
+    }
+}
+
+impl<'a, T: PositionNum + fmt::Display> fmt::Display for Expr<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "0");
+        }
+        for (idx, (asset, sv)) in self.0.values.iter().enumerate() {
+            let mut value = sv.value().clone();
+            let first_sv = idx == 0;
+            let no_position = sv.is_empty();
+            for (idx, p) in sv.positions.values().enumerate() {
+                if !(first_sv && idx == 0) {
+                    write!(f, " + ")?;
+                }
+                value += p.value();
+                let naive = p.as_naive();
+                super::tree::write_position(f, &naive.price, &naive.size, p.instrument())?;
+            }
+            if first_sv && no_position {
+                write!(f, "{} {asset}", value)?;
+            } else {
+                let sign = if value.is_negative() { " - " } else { " + " };
+                write!(f, "{sign}{} {asset}", value.abs())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asset::Asset, Reversed};
+    use fraction::{BigInt, GenericDecimal, Zero};
+
+    type Decimal = GenericDecimal<BigInt, usize>;
+
+    #[test]
+    fn normal() {
+        let mut p = Position::new(
+            Instrument::from((Asset::btc(), Asset::usdt())),
+            Decimal::zero(),
+        );
+        p += Decimal::from(1.5);
+        p += (Decimal::from(2.3), Decimal::from(2.5));
+        p += (Decimal::from(7.3), Decimal::from(3.4));
+        p += (Decimal::from(3.7), Decimal::from(-7.8), Decimal::from(12));
+        #[cfg(feature = "std")]
+        println!("{p}");
+        assert_eq!(
+            p,
+            Position::new(
+                Instrument::from((Asset::btc(), Asset::usdt())),
+                (Decimal::from(3.7), Decimal::from(-1.9), Decimal::from(4.7),)
+            )
+        );
+        #[cfg(feature = "std")]
+        println!("{}", p.as_tree());
+    }
+
+    #[test]
+    fn reversed() {
+        let mut p = Position::new(
+            Instrument::from((Asset::usdt(), Asset::btc())).prefer_reversed(true),
+            Decimal::zero(),
+        );
+        p += Reversed(Decimal::from(1.5));
+        p += Reversed((Decimal::from(3), Decimal::from(2)));
+        p += Reversed((Decimal::from(4), Decimal::from(1)));
+        p += Reversed((Decimal::from(2), Decimal::from(-7), Decimal::from(-1.4)));
+        #[cfg(feature = "std")]
+        println!("{p}");
+        assert_eq!(
+            *p.value(),
+            (Decimal::from(-29) / Decimal::from(60)).set_precision(1),
+        );
+        #[cfg(feature = "std")]
+        println!("{}", p.as_tree());
+    }
+
+    #[test]
+    fn basic_positions() {
+        let btc = Asset::btc();
+        let usdt = Asset::usdt();
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
+            .unwrap()
+            .prefer_reversed(true);
+        let eth_btc_swap =
+            Instrument::try_new("SWAP:ETH-BTC-SWAP", &Asset::eth(), &Asset::btc()).unwrap();
+        let mut p = Positions::default();
+        p += (Decimal::from(-16000), &usdt);
+        p += (Decimal::from(1), &btc);
+        p += Reversed((Decimal::from(16000), Decimal::from(-16000), &btc_usd_swap));
+        p += (Decimal::from(0.067), Decimal::from(-21.5), &eth_btc_swap);
+        p += (
+            Decimal::from(16001),
+            Decimal::from(-1.5),
+            Decimal::from(-2.7),
+            &btc_usdt_swap,
+        );
+        #[cfg(feature = "std")]
+        println!("{p}");
+    }
+
+    #[test]
+    fn positions_as_tree() {
+        let btc = Asset::btc();
+        let usdt = Asset::usdt();
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
+            .unwrap()
+            .prefer_reversed(true);
+        let eth_btc_swap =
+            Instrument::try_new("SWAP:ETH-BTC-SWAP", &Asset::eth(), &Asset::btc()).unwrap();
+        let mut p = Positions::default();
+        p += (Decimal::from(-16000), &usdt);
+        p += (Decimal::from(1), &btc);
+        p += Reversed((Decimal::from(16000), Decimal::from(-16000), &btc_usd_swap));
+        p += (Decimal::from(0.067), Decimal::from(-21.5), &eth_btc_swap);
+        p += (
+            Decimal::from(16001),
+            Decimal::from(-1.5),
+            Decimal::from(-2.7),
+            &btc_usdt_swap,
+        );
+        let tree = p.as_tree(&usdt);
+        #[cfg(feature = "std")]
+        println!("{tree}");
+        let prices = HashMap::from([
+            (eth_btc_swap.clone(), Decimal::from(0.059)),
+            (btc_usd_swap.clone(), Decimal::from(17000)),
+            (btc_usdt_swap.clone(), Decimal::from(17002)),
+            (
+                Instrument::from((btc.clone(), usdt.clone())),
+                Decimal::from(17000),
+            ),
+        ]);
+        #[cfg(feature = "std")]
+        for inst in tree.instruments() {
+            println!("{inst}");
+        }
+        let ans = tree.eval(&prices).unwrap().set_precision(1);
+        #[cfg(feature = "std")]
+        println!("{ans}");
+        assert_eq!(ans, Decimal::from(1419.8).set_precision(1));
+        assert_eq!(
+            p.as_expr()
+                .eval_by_instrument(&usdt, &prices)
+                .unwrap()
+                .set_precision(1),
+            ans
+        );
+        let mut missing = prices.clone();
+        missing.remove(&eth_btc_swap);
+        let err = tree.try_eval(&missing).unwrap_err();
+        assert_eq!(&err.symbol, eth_btc_swap.as_symbol());
+        assert_eq!((err.base, err.quote), (Asset::eth(), btc.clone()));
+    }
+
+    #[test]
+    fn cross_rate() {
+        let prices = HashMap::from([
+            (Symbol::spot(&Asset::eth(), &Asset::usdt()), 1200.0),
+            (Symbol::spot(&Asset::btc(), &Asset::usdt()), 16000.0),
+            (Symbol::spot(&Asset::usdt(), &Asset::usd()), 1.0),
+        ]);
+        let (eth, btc, usdt, usd) = (Asset::eth(), Asset::btc(), Asset::usdt(), Asset::usd());
+        assert_eq!(super::cross_rate(&prices, &eth, &btc, &usdt), Some(0.075));
+        assert_eq!(
+            super::cross_rate(&prices, &btc, &eth, &usdt),
+            Some(16000.0 / 1200.0)
+        );
+        assert_eq!(
+            super::cross_rate(&prices, &btc, &usdt, &usdt),
+            Some(16000.0)
+        );
+        assert_eq!(
+            super::cross_rate(&prices, &usd, &btc, &usdt),
+            Some(1.0 / 16000.0)
+        );
+        assert_eq!(super::cross_rate(&prices, &eth, &usd, &btc), None);
+    }
+
+    #[test]
+    fn fees() {
+        use rust_decimal_macros::dec;
+
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let mut p = Positions::default();
+        p += (dec!(1000), &Asset::usdt());
+        p += (dec!(16000), dec!(1), &btc_usdt_swap);
+        p.charge_fee(dec!(8), &Asset::usdt())
+            .charge_fee(dec!(0.0001), &Asset::btc());
+        assert_eq!(p.get_value(&Asset::usdt()), Some(&dec!(992)));
+        assert_eq!(p.fees()[&Asset::btc()], dec!(0.0001));
+        let prices = HashMap::from([
+            (btc_usdt_swap.as_symbol().clone(), dec!(17000)),
+            (Symbol::spot(&Asset::btc(), &Asset::usdt()), dec!(17000)),
+        ]);
+        assert_eq!(p.total_fees(&Asset::usdt(), &prices), Some(dec!(9.7)));
+        assert_eq!(
+            p.equity_with_fees(&Asset::usdt(), &prices),
+            Some((dec!(2000), dec!(1990.3)))
+        );
+        let bnb: Asset = "BNB".parse().unwrap();
+        let mut r = p.clone();
+        r.charge_fee(dec!(0.01), &bnb);
+        r += (dec!(0.01), &bnb);
+        r.prune_dust(&dec!(0), &dec!(0));
+        let mut prices = prices;
+        assert!(r.as_expr().eval(&Asset::usdt(), &prices).is_some());
+        prices.insert(Symbol::spot(&Asset::usdt(), &bnb), dec!(0.004));
+        assert_eq!(
+            r.equity_with_fees(&Asset::usdt(), &prices),
+            Some((dec!(2002.5), dec!(1990.3)))
+        );
+        let q = p.clone() + p.clone();
+        assert_eq!(q.fees()[&Asset::usdt()], dec!(16));
+        assert_ne!(
+            q,
+            p.clone() + Positions::from(p.get_position(&btc_usdt_swap).cloned().unwrap())
+        );
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn arbitrary_commutative() -> anyhow::Result<()> {
+        use arbitrary::{Arbitrary, Unstructured};
+        use fraction::BigFraction;
+
+        let mut seed = 0x9e37_79b9_7f4a_7c15_u64;
+        let data = (0..16384)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect::<Vec<_>>();
+        let mut u = Unstructured::new(&data);
+        let num = |u: &mut Unstructured| -> arbitrary::Result<BigFraction> {
+            Ok(BigFraction::from(u.int_in_range(-50i64..=50)?))
+        };
+        for _ in 0..16 {
+            let instruments = (0..3)
+                .map(|_| Instrument::arbitrary(&mut u))
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut tables = [Positions::default(), Positions::default()];
+            for table in tables.iter_mut() {
+                for _ in 0..u.int_in_range(0..=6)? {
+                    let inst = u.choose(&instruments)?;
+                    let price = BigFraction::from(u.int_in_range(1i64..=100)?);
+                    let position = (price, num(&mut u)?, num(&mut u)?);
+                    table.insert_position(inst.position(position));
+                    table.insert_value(num(&mut u)?, inst.base());
+                }
+            }
+            let [lhs, rhs] = tables;
+            assert_eq!(lhs.clone() + rhs.clone(), rhs + lhs);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn convert_all() {
+        use rust_decimal_macros::dec;
+
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
+            .unwrap()
+            .prefer_reversed(true);
+        let eth_usdt_swap =
+            Instrument::try_new("SWAP:ETH-USDT-SWAP", &Asset::eth(), &Asset::usdt()).unwrap();
+        let mut p = Positions::default();
+        p += (dec!(16000), dec!(1), dec!(-2), &btc_usdt_swap);
+        p += Reversed((dec!(16000), dec!(-1600), &btc_usd_swap));
+        p += (dec!(1200), dec!(1), &eth_usdt_swap);
+        let mut prices = HashMap::from([
+            (btc_usdt_swap.as_symbol().clone(), dec!(17000)),
+            (btc_usd_swap.as_symbol().clone(), dec!(0)),
+        ]);
+        let before = p.clone();
+        assert!(matches!(
+            p.convert_all(&prices, false),
+            Err(ConvertError::MissingPrice(_) | ConvertError::ZeroPrice(_))
+        ));
+        assert_eq!(p, before);
+        let mut skipped = p.convert_all(&prices, true).unwrap();
+        skipped.sort();
+        assert_eq!(
+            skipped,
+            [
+                btc_usd_swap.as_symbol().clone(),
+                eth_usdt_swap.as_symbol().clone()
+            ]
+        );
+        assert_eq!(p, before);
+        let btc = p.get_position(&btc_usdt_swap).unwrap();
+        assert_eq!(btc.price(), Some(dec!(17000)));
+        assert_eq!(btc.value(), &dec!(998));
+        prices.insert(btc_usd_swap.as_symbol().clone(), dec!(20000));
+        prices.insert(eth_usdt_swap.as_symbol().clone(), dec!(1000));
+        let closed = p.get_position(&btc_usd_swap).unwrap().closed(&dec!(20000));
+        assert!(p.convert_all(&prices, false).unwrap().is_empty());
+        let usd = p.get_position(&btc_usd_swap).unwrap();
+        assert_eq!(usd.price(), Some(dec!(20000)));
+        assert_eq!(usd.value(), &closed);
+        assert_eq!(p.get_position(&eth_usdt_swap).unwrap().value(), &dec!(-200));
+    }
+
+    #[test]
+    fn single_value_total_value() {
+        use rust_decimal_macros::dec;
+
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let eth_usdt_swap =
+            Instrument::try_new("SWAP:ETH-USDT-SWAP", &Asset::eth(), &Asset::usdt()).unwrap();
+        let mut p = Positions::default();
+        p += (dec!(100), &Asset::usdt());
+        p += (dec!(16000), dec!(1), dec!(-2), &btc_usdt_swap);
+        p += (dec!(1200), dec!(-2), &eth_usdt_swap);
+        let mut prices = HashMap::from([(btc_usdt_swap.as_symbol().clone(), dec!(17000))]);
+        let (_, sv) = p
+            .iter()
+            .find(|(asset, _)| **asset == Asset::usdt())
+            .unwrap();
+        assert!(sv.total_value(&prices).is_none());
+        prices.insert(eth_usdt_swap.as_symbol().clone(), dec!(1000));
+        assert_eq!(sv.total_value(&prices), Some(dec!(1498)));
+        assert_eq!(
+            sv.total_value(&prices),
+            p.as_expr().eval(&Asset::usdt(), &prices)
+        );
+    }
+
+    #[test]
+    fn owned_expr() {
+        fn book() -> OwnedExpr<Decimal> {
+            let btc_usdt_swap =
+                Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+            let mut p = Positions::default();
+            p += (Decimal::from(1), &Asset::btc());
+            p += (Decimal::from(16000), Decimal::from(-1), &btc_usdt_swap);
+            p.into_expr()
+        }
+
+        let expr = book();
+        let usdt = Asset::usdt();
+        let mut instruments = expr.instruments(&usdt).collect::<Vec<_>>();
+        instruments.sort();
+        let mut expected = expr.as_expr().instruments(&usdt).collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(instruments, expected);
+        let prices = HashMap::from([
+            (
+                Symbol::from_str("SWAP:BTC-USDT-SWAP").unwrap(),
+                Decimal::from(17000),
+            ),
+            (Symbol::spot(&Asset::btc(), &usdt), Decimal::from(17000)),
+        ]);
+        assert_eq!(expr.eval(&usdt, &prices), Some(Decimal::from(16000)));
+        assert_eq!(
+            expr.compile(&usdt).eval(&prices),
+            Some(Decimal::from(16000))
+        );
+        assert_eq!(expr.to_string(), expr.as_expr().to_string());
+        assert_eq!(expr.clone().into_inner(), *expr);
+    }
+
+    #[test]
+    fn closed_matches_merging() {
+        fn merged(p: &Position<f64>, price: f64) -> f64 {
+            let mut naive = p.naive;
+            if p.instrument.is_prefer_reversed() {
+                naive -= Reversed((price, p.size()));
+            } else {
+                naive -= (price, p.size());
+            }
+            naive.value
+        }
+
+        let spot = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::USD, &Asset::BTC)
+            .unwrap()
+            .prefer_reversed(true);
+        for inst in [spot, swap] {
+            for (price, size, value) in [
+                (16000.1, 0.3, 1.7),
+                (16000.1, -0.3, -2.9),
+                (0.1, 123.456, 0.0),
+                (1.0, 0.0, 3.3),
+            ] {
+                let p = Position::new(inst.clone(), (price, size, value));
+                for mark in [0.3_f64, 15999.9, 17123.45] {
+                    assert_eq!(p.closed(&mark).to_bits(), merged(&p, mark).to_bits());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn into_positions() {
+        let btc_usdt = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let eth_btc = Instrument::spot(&Asset::ETH, &Asset::BTC);
+        let mut p = Asset::USDT.value(Decimal::from(1));
+        p += (Decimal::from(2), Decimal::from(1), &btc_usdt);
+        p += Position::zero(&eth_btc);
+        let positions = p.into_positions().collect::<Vec<_>>();
+        assert_eq!(positions.len(), 1);
+        let (symbol, position) = &positions[0];
+        assert_eq!(symbol, btc_usdt.as_symbol());
+        assert_eq!(position.size(), Decimal::from(1));
+    }
+
+    #[test]
+    fn equity_tracker() {
+        use rust_decimal_macros::dec;
+
+        let btc_usdt = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let mut p = Asset::USDT.value(dec!(1000));
+        p += (dec!(20000), dec!(0.1), &btc_usdt);
+        let mut tracker = EquityTracker::new(&Asset::USDT);
+        assert_eq!(tracker.drawdown(), dec!(0));
+        assert_eq!(tracker.peak(), None);
+        for (price, equity) in [
+            (dec!(21000), dec!(1100)),
+            (dec!(19000), dec!(900)),
+            (dec!(22000), dec!(1200)),
+            (dec!(21000), dec!(1100)),
+        ] {
+            let prices = HashMap::from([(btc_usdt.as_symbol().clone(), price)]);
+            assert_eq!(tracker.update(&p, &prices), Some(&equity));
+        }
+        assert_eq!(tracker.peak(), Some(&dec!(1200)));
+        assert_eq!(tracker.drawdown(), dec!(100));
+        assert_eq!(tracker.max_drawdown(), &dec!(200));
+        assert!(tracker.update(&p, &HashMap::default()).is_none());
+        assert_eq!(tracker.equity(), Some(&dec!(1100)));
+    }
+
+    #[test]
+    fn amortize_funding() {
+        use rust_decimal_macros::dec;
+
+        let inst = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let mut p = inst.position((dec!(20000), dec!(2), dec!(3)));
+        let mut q = p.clone();
+        p.amortize_funding(&dec!(-10));
+        q += dec!(-10);
+        assert_eq!(p.price(), Some(dec!(20005)));
+        assert_eq!(p.value(), &dec!(3));
+        assert_eq!(p, q);
+        let mut z = Position::zero(&inst);
+        z.amortize_funding(&dec!(1));
+        assert!(z.is_zero());
+        let swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::USD, &Asset::BTC)
+            .unwrap()
+            .prefer_reversed(true);
+        let mut p = swap.position(Reversed((dec!(20000), dec!(-100))));
+        let mut q = p.clone();
+        p.amortize_funding(&dec!(0.0025));
+        q += dec!(0.0025);
+        assert_eq!(p, q);
+        assert_eq!(p.price(), Some(dec!(40000)));
+    }
+
+    #[test]
+    fn payoff_curve() {
+        use rust_decimal_macros::dec;
+
+        let btc_usdt = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::BTC, &Asset::USDT).unwrap();
+        let mut p = Asset::USDT.value(dec!(1000));
+        p += (dec!(20000), dec!(1), &btc_usdt);
+        p += (dec!(20000), dec!(-1), &btc_usdt_swap);
+        p += (dec!(-0.5), &Asset::BTC);
+        let prices = HashMap::from([
+            (btc_usdt.as_symbol().clone(), dec!(20000)),
+            (btc_usdt_swap.as_symbol().clone(), dec!(20000)),
+            (Symbol::spot(&Asset::USDT, &Asset::BTC), dec!(0.00005)),
+        ]);
+        let curve = p.payoff_curve(
+            &btc_usdt,
+            &prices,
+            &[dec!(10000), dec!(20000), dec!(0)],
+            &Asset::USDT,
+        );
+        assert_eq!(curve, [Some(dec!(-14000)), Some(dec!(-9000)), None]);
+        let curve = p.payoff_curve(&btc_usdt_swap, &prices, &[dec!(10000)], &Asset::USDT);
+        assert_eq!(curve, [Some(dec!(1000))]);
+        let curve = p.payoff_curve(&btc_usdt, &prices, &[dec!(10000)], &Asset::BTC);
+        assert_eq!(curve, [Some(dec!(-1.4))]);
+    }
+
+    #[test]
+    fn eval_in() {
+        use rust_decimal_macros::dec;
+
+        let btc_usdt = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let mut p = Asset::USDT.value(dec!(1000));
+        p += (dec!(20000), dec!(1), &btc_usdt);
+        let prices = HashMap::from([
+            (btc_usdt.as_symbol().clone(), dec!(21000)),
+            (Symbol::spot(&Asset::USDT, &Asset::USD), dec!(1)),
+        ]);
+        assert_eq!(p.eval_in(&Asset::USDT, &prices), Some(dec!(2000)));
+        assert_eq!(p.eval_in(&Asset::USD, &prices), Some(dec!(2000)));
+        assert_eq!(p.eval_in(&Asset::ETH, &prices), None);
+        let empty = Positions::default();
+        assert_eq!(empty.eval_in(&Asset::ETH, &prices), Some(dec!(0)));
+    }
+
+    #[test]
+    fn try_merge() {
+        use rust_decimal_macros::dec;
+
+        let swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::USD, &Asset::BTC).unwrap();
+        let reversed = swap.clone().prefer_reversed(true);
+        let mut p = swap.position((dec!(20000), dec!(1)));
+        let mut q = reversed.position((dec!(20000), dec!(1)));
+        let err = p.try_merge(&mut q).unwrap_err();
+        assert!(is_identical(&err.lhs, &swap) && is_identical(&err.rhs, &reversed));
+        assert!(!q.is_zero());
+        let mut q = swap.position((dec!(21000), dec!(-2)));
+        assert_eq!(p.try_merge(&mut q).unwrap(), dec!(1000));
+        assert!(q.is_zero());
+        assert_eq!(p.price(), Some(dec!(21000)));
+        assert_eq!(p.size(), dec!(-1));
+    }
+
+    #[test]
+    fn approx_eq() {
+        use rust_decimal_macros::dec;
+
+        let inst = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let third = dec!(1) / dec!(3);
+        let p = inst.position((third * dec!(3), dec!(2), dec!(1)));
+        let q = inst.position((dec!(1), dec!(2), dec!(1)));
+        assert_ne!(p, q);
+        assert!(p.approx_eq(&q, &dec!(1e-20)));
+        let r = inst.position((dec!(1.1), dec!(2), dec!(2)));
+        assert!(!p.approx_eq(&r, &dec!(1e-20)));
+        let other = Instrument::spot(&Asset::ETH, &Asset::USDT);
+        assert!(!p.approx_eq(&other.position(p.to_naive()), &dec!(1)));
+    }
+
+    #[test]
+    fn ledger() {
+        use rust_decimal_macros::dec;
+
+        let btc_usdt = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let mut p = Positions::default();
+        p += (dec!(1), &Asset::USDT);
+        assert!(!p.is_recording());
+        assert!(p.ledger().is_empty());
+        p.start_recording();
+        p += (dec!(1000), &Asset::USDT);
+        p += (dec!(20000), dec!(1), &btc_usdt);
+        p += (dec!(21000), dec!(-0.5), &btc_usdt);
+        p.charge_fee(dec!(2), &Asset::USDT);
+        p.concentrate();
+        let usdt = |amount, reason| LedgerEntry {
+            asset: Asset::USDT,
+            amount,
+            reason,
+        };
+        let symbol = btc_usdt.as_symbol().clone();
+        assert_eq!(
+            p.ledger(),
+            [
+                usdt(dec!(1000), LedgerReason::Insert),
+                usdt(dec!(500), LedgerReason::Realize(symbol.clone())),
+                usdt(dec!(-2), LedgerReason::Fee),
+                usdt(dec!(500), LedgerReason::Concentrate(symbol)),
+            ]
+        );
+        assert_eq!(p.get_value(&Asset::USDT), Some(&dec!(1499)));
+        p.clear_ledger();
+        assert!(p.is_recording());
+        assert!(p.ledger().is_empty());
+        p += (dec!(1), &Asset::BTC);
+        assert_eq!(p.stop_recording().len(), 1);
+        assert!(!p.is_recording());
+    }
+
+    #[test]
+    fn get_same_market() {
+        let btc_usdt = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let usdt_btc = btc_usdt.to_reversed_spot().unwrap();
+        let mut p = Positions::default();
+        p += (Decimal::from(2), Decimal::from(1), &usdt_btc);
+        let found = p.get_same_market(&btc_usdt).unwrap();
+        assert_eq!(found.instrument().as_symbol(), usdt_btc.as_symbol());
+        assert!(p.get_position(&btc_usdt).is_none());
+        let eth_usdt = Instrument::spot(&Asset::ETH, &Asset::USDT);
+        assert!(p.get_same_market(&eth_usdt).is_none());
+    }
+
+    #[test]
+    fn extend_values() {
+        let balances: HashMap<_, _> = HashMap::from([
+            (Asset::BTC, Decimal::from(1)),
+            (Asset::USDT, Decimal::from(1000)),
+        ]);
+        let mut p = Asset::USDT.value(Decimal::from(1));
+        p.extend(balances.iter().map(|(asset, value)| (value.clone(), asset)));
+        p.extend([(Decimal::from(2), &Asset::ETH)]);
+        assert_eq!(p.get_value(&Asset::BTC), Some(&Decimal::from(1)));
+        assert_eq!(p.get_value(&Asset::USDT), Some(&Decimal::from(1001)));
+        assert_eq!(p.get_value(&Asset::ETH), Some(&Decimal::from(2)));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn snapshot() {
+        use rust_decimal_macros::dec;
+
+        let btc_usdt = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let mut p = Asset::USDT.value(dec!(1000));
+        p += (dec!(20000), dec!(1), &btc_usdt);
+        let snapshot = p.snapshot();
+        let prices = HashMap::from([(btc_usdt.as_symbol().clone(), dec!(21000))]);
+        let reader = {
+            let snapshot = Arc::clone(&snapshot);
+            let prices = prices.clone();
+            std::thread::spawn(move || snapshot.as_expr().eval(&Asset::USDT, &prices))
+        };
+        p += (dec!(21000), dec!(-1), &btc_usdt);
+        assert_eq!(reader.join().unwrap(), Some(dec!(2000)));
+        assert_eq!(
+            snapshot.as_expr().eval(&Asset::USDT, &prices),
+            Some(dec!(2000))
+        );
+        assert_ne!(*snapshot, p);
+    }
+
+    #[test]
+    fn accrue_borrow() {
+        use rust_decimal_macros::dec;
+
+        let mut p = Asset::BTC.value(dec!(-2));
+        p += (dec!(1000), &Asset::USDT);
+        p.start_recording();
+        assert_eq!(p.accrue_borrow(&Asset::BTC, &dec!(0.01)), dec!(-0.02));
+        assert_eq!(p.get_value(&Asset::BTC), Some(&dec!(-2.02)));
+        assert_eq!(p.accrue_borrow(&Asset::USDT, &dec!(0.01)), dec!(0));
+        assert_eq!(p.accrue_borrow(&Asset::ETH, &dec!(0.01)), dec!(0));
+        assert_eq!(p.get_value(&Asset::USDT), Some(&dec!(1000)));
+        assert_eq!(p.ledger().len(), 1);
+        assert_eq!(p.ledger()[0].reason, LedgerReason::Borrow);
+    }
+
+    #[test]
+    fn close_all() {
+        use rust_decimal_macros::dec;
+
+        let btc_usdt = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::USD, &Asset::BTC)
+            .unwrap()
+            .prefer_reversed(true);
+        let eth_usd_quanto =
+            Instrument::derivative("FUTURES", "ETH-USD-QUANTO", &Asset::ETH, &Asset::BTC)
+                .unwrap()
+                .quanto(&Asset::USD);
+        let sol_usd_quanto = Instrument::derivative(
+            "FUTURES",
+            "SOL-USD-QUANTO",
+            &Asset::try_from("SOL").unwrap(),
+            &Asset::BTC,
+        )
+        .unwrap()
+        .quanto(&Asset::USD);
+        let mut p = Asset::USDT.value(dec!(100));
+        p += (dec!(20000), dec!(1), dec!(-5), &btc_usdt);
+        p += Reversed((dec!(20000), dec!(-20000), &btc_usd_swap));
+        p += (dec!(1000), dec!(1), &eth_usd_quanto);
+        p += (dec!(20), dec!(10), &sol_usd_quanto);
+        p += (dec!(30), dec!(-10), &sol_usd_quanto);
+        let mut prices = HashMap::from([
+            (btc_usdt.as_symbol().clone(), dec!(21000)),
+            (btc_usd_swap.as_symbol().clone(), dec!(25000)),
+            (eth_usd_quanto.as_symbol().clone(), dec!(1100)),
+        ]);
+        let before = p.clone();
+        let err = p.close_all(&prices).unwrap_err();
+        assert_eq!(err.symbol, Symbol::spot(&Asset::USD, &Asset::BTC));
+        assert_eq!(p, before);
+        prices.insert(Symbol::spot(&Asset::USD, &Asset::BTC), dec!(0.00005));
+        let cash = p.close_all(&prices).unwrap();
+        assert_eq!(cash[&Asset::USDT], dec!(995));
+        assert_eq!(cash[&Asset::BTC], dec!(-0.2) + dec!(0.005) + dec!(0.005));
+        assert!(p.iter_mut().next().is_none());
+        assert_eq!(p.get_value(&Asset::USDT), Some(&dec!(1095)));
+    }
+
+    #[test]
+    fn eval_layered() {
+        use rust_decimal_macros::dec;
+
+        let btc_usdt = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let eth_usdt = Instrument::spot(&Asset::ETH, &Asset::USDT);
+        let mut p = Asset::USDT.value(dec!(100));
+        p += (dec!(20000), dec!(1), &btc_usdt);
+        p += (dec!(1000), dec!(1), &eth_usdt);
+        let live = HashMap::from([(btc_usdt.as_symbol().clone(), dec!(21000))]);
+        let last = HashMap::from([
+            (btc_usdt.as_symbol().clone(), dec!(19000)),
+            (eth_usdt.as_symbol().clone(), dec!(1100)),
+        ]);
+        let expr = p.as_expr();
+        assert_eq!(
+            expr.eval_layered(&Asset::USDT, &[&live, &last]),
+            Some(dec!(1200))
+        );
+        assert_eq!(
+            expr.eval_layered(&Asset::USDT, &[&last, &live]),
+            Some(dec!(-800))
+        );
+        assert_eq!(expr.eval_layered(&Asset::USDT, &[&live]), None);
+        assert_eq!(expr.eval_layered(&Asset::USDT, &[]), None);
+    }
+
+    #[test]
+    fn assert_balanced() {
+        use rust_decimal_macros::dec;
+
+        let btc_usdt = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let eth_btc = Instrument::spot(&Asset::ETH, &Asset::BTC);
+        let mut p = Asset::USDT.value(dec!(1000));
+        p += (dec!(20000), dec!(0.5), &btc_usdt);
+        p += (dec!(0.05), dec!(2), dec!(-0.1), &eth_btc);
+        let prices = HashMap::from([
+            (btc_usdt.as_symbol().clone(), dec!(21000)),
+            (eth_btc.as_symbol().clone(), dec!(0.06)),
+        ]);
+        assert!(p.assert_balanced(&Asset::USDT, &prices, &dec!(0)).is_ok());
+        let mut q = Asset::USDT.value(dec!(1000));
+        q += (dec!(0.5), &Asset::BTC);
+        q += (dec!(0.05), dec!(2), dec!(-0.1), &eth_btc);
+        let reversed = HashMap::from([
+            (eth_btc.as_symbol().clone(), dec!(0.06)),
+            (Symbol::spot(&Asset::USDT, &Asset::BTC), dec!(0.00005)),
+        ]);
+        assert!(q.assert_balanced(&Asset::USDT, &reversed, &dec!(0)).is_ok());
+        assert!(matches!(
+            p.assert_balanced(&Asset::USDT, &HashMap::default(), &dec!(0)),
+            Err(ImbalanceError::MissingPrices)
+        ));
+        let mut corrupted = p.clone();
+        corrupted
+            .values
+            .get_mut(&Asset::USDT)
+            .unwrap()
+            .positions
+            .get_mut(btc_usdt.as_symbol())
+            .unwrap()
+            .naive
+            .price = dec!(-20000);
+        assert!(corrupted.as_expr().eval(&Asset::USDT, &prices).is_some());
+        assert!(matches!(
+            corrupted.assert_balanced(&Asset::USDT, &prices, &dec!(0)),
+            Err(ImbalanceError::Invalid { symbol, .. }) if symbol == *btc_usdt.as_symbol()
+        ));
+        let sv = p.values.get_mut(&Asset::BTC).unwrap();
+        let misfiled = sv.positions.remove(eth_btc.as_symbol()).unwrap();
+        p.values
+            .get_mut(&Asset::USDT)
+            .unwrap()
+            .positions
+            .insert(eth_btc.as_symbol().clone(), misfiled);
+        assert!(matches!(
+            p.assert_balanced(&Asset::USDT, &prices, &dec!(0)),
+            Err(ImbalanceError::Misfiled(symbol)) if symbol == *eth_btc.as_symbol()
+        ));
+
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::BTC, &Asset::USDT).unwrap();
+        let mut p = Asset::USDT.value(0.1);
+        p += (0.05, 2.0, 0.2, &eth_btc);
+        p += (20000.1, 0.3, &btc_usdt);
+        p += (19999.7, -0.7, 0.1, &btc_usdt_swap);
+        let prices = HashMap::from([
+            (btc_usdt.as_symbol().clone(), 21000.3),
+            (btc_usdt_swap.as_symbol().clone(), 21000.7),
+            (eth_btc.as_symbol().clone(), 0.06),
+        ]);
+        assert!(p.assert_balanced(&Asset::USDT, &prices, &1e-9).is_ok());
+        let prices = HashMap::from([
+            (btc_usdt.as_symbol().clone(), f64::NAN),
+            (btc_usdt_swap.as_symbol().clone(), 21000.7),
+            (eth_btc.as_symbol().clone(), 0.06),
+        ]);
+        assert!(matches!(
+            p.assert_balanced(&Asset::USDT, &prices, &1e-9),
+            Err(ImbalanceError::Mismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn pnl_bps() {
+        use rust_decimal_macros::dec;
+
+        let btc_usdt = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let p = Position::new(btc_usdt.clone(), (dec!(20000), dec!(-2), dec!(5)));
+        assert_eq!(p.pnl_bps(&dec!(19000)), Some(dec!(500)));
+        assert_eq!(Position::zero(&btc_usdt).pnl_bps(&dec!(1)), None);
+        let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::USD, &Asset::BTC)
+            .unwrap()
+            .prefer_reversed(true);
+        let p = Position::from_display_parts(btc_usd_swap, dec!(20000), dec!(-20000));
+        let bps = p.pnl_bps(&dec!(25000)).unwrap();
+        assert_eq!(bps, dec!(-2000));
+        assert_eq!(p.pnl_bps(&dec!(0)), None);
+        assert_eq!(super::annualize(&dec!(0.01), &dec!(73)), dec!(0.05));
+    }
+
+    #[test]
+    fn subscribe_symbols() {
+        let btc_usdt = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::USD, &Asset::BTC)
+            .unwrap()
+            .prefer_reversed(true);
+        let mut p = Asset::USDT.value(Decimal::from(1));
+        p += (Decimal::from(2), Decimal::from(1), &btc_usdt);
+        p += (Decimal::from(3), Decimal::from(1), &btc_usd_swap);
+        p += (Decimal::from(1), &Asset::BTC);
+        let mut expected = alloc::vec![
+            btc_usdt.as_symbol().clone(),
+            btc_usd_swap.as_symbol().clone(),
+        ];
+        expected.sort();
+        assert_eq!(p.subscribe_symbols(&Asset::USDT), expected);
+    }
+
+    #[test]
+    fn try_from_positions() {
+        let btc_usdt = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let eth_usdt = Instrument::spot(&Asset::ETH, &Asset::USDT);
+        let mut p = Positions::from(Position::new(
+            btc_usdt.clone(),
+            (Decimal::from(2), Decimal::from(1), Decimal::from(3)),
+        ));
+        p += (Decimal::from(4), &Asset::USDT);
+        p += Position::zero(&eth_usdt);
+        let q = Position::try_from(p.clone()).unwrap();
+        assert_eq!(q.instrument(), &btc_usdt);
+        assert_eq!(
+            q,
+            Position::new(
+                btc_usdt,
+                (Decimal::from(2), Decimal::from(1), Decimal::from(7))
+            )
+        );
+        let mut r = p.clone();
+        r += (Decimal::from(1), &Asset::BTC);
+        assert!(matches!(
+            Position::try_from(r),
+            Err(IntoPositionError::ForeignValue(asset)) if asset == Asset::BTC
+        ));
+        p += (Decimal::from(1), Decimal::from(1), &eth_usdt);
+        assert!(matches!(
+            Position::try_from(p),
+            Err(IntoPositionError::Ambiguous)
+        ));
+        assert!(matches!(
+            Position::try_from(Asset::USDT.value(Decimal::from(1))),
+            Err(IntoPositionError::Empty)
+        ));
+        let eth_usd_quanto =
+            Instrument::derivative("FUTURES", "ETH-USD-QUANTO", &Asset::ETH, &Asset::BTC)
+                .unwrap()
+                .quanto(&Asset::USD);
+        let mut p = Asset::BTC.value(Decimal::from(1));
+        p += (Decimal::from(1000), Decimal::from(1), &eth_usd_quanto);
+        assert!(matches!(
+            Position::try_from(p.clone()),
+            Err(IntoPositionError::ForeignValue(asset)) if asset == Asset::BTC
+        ));
+        p.get_value_mut(&Asset::BTC).unwrap().set_zero();
+        assert_eq!(Position::try_from(p).unwrap().instrument(), &eth_usd_quanto);
+    }
+
+    #[test]
+    fn rebalance_to() {
+        use rust_decimal_macros::dec;
+
+        let btc_usdt = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let mut p = Asset::USDT.value(dec!(10000));
+        p += (dec!(20000), dec!(0.5), &btc_usdt);
+        let mut prices = HashMap::from([
+            (Symbol::spot(&Asset::BTC, &Asset::USDT), dec!(20000)),
+            (Symbol::spot(&Asset::USDT, &Asset::ETH), dec!(0.001)),
+        ]);
+        let weights = HashMap::from([
+            (Asset::BTC, dec!(0.25)),
+            (Asset::ETH, dec!(0.5)),
+            (Asset::USDT, dec!(0.25)),
+        ]);
+        let trades = p.rebalance_to(&weights, &Asset::USDT, &prices).unwrap();
+        assert_eq!(
+            trades,
+            [
+                (btc_usdt, dec!(-0.375)),
+                (Instrument::spot(&Asset::ETH, &Asset::USDT), dec!(5)),
+            ]
+        );
+        prices.remove(&Symbol::spot(&Asset::USDT, &Asset::ETH));
+        assert!(p.rebalance_to(&weights, &Asset::USDT, &prices).is_none());
+    }
+
+    #[test]
+    fn eq_canonical() {
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let eth_usdt_swap =
+            Instrument::try_new("SWAP:ETH-USDT-SWAP", &Asset::eth(), &Asset::usdt()).unwrap();
+        let mut p = Positions::<Decimal>::default();
+        p += (Decimal::from(16000), Decimal::from(1), &btc_usdt_swap);
+        let mut q = p.clone();
+        q.entry_position(&eth_usdt_swap);
+        q.insert_value(Decimal::from(0), &Asset::eth());
+        q.charge_fee(Decimal::from(0), &Asset::usdt());
+        assert_ne!(p, q);
+        assert!(p.eq_canonical(&q));
+        assert!(q.eq_canonical(&p));
+        q += (Decimal::from(1200), Decimal::from(1), &eth_usdt_swap);
+        assert!(!p.eq_canonical(&q));
+        assert!(!q.eq_canonical(&p));
+    }
+
+    #[test]
+    fn position_meta() {
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let p = Position::new(btc_usdt_swap.clone(), (16000.0, 1.0)).with_meta("trend");
+        let mut q = Position::new(btc_usdt_swap, (17000.0, 1.0)).with_meta("carry");
+        assert_eq!(*q.meta(), "carry");
+        *q.meta_mut() = "mean-reversion";
+        let merged = p.clone() + q;
+        assert_eq!(*merged.meta(), "trend");
+        assert_eq!(merged.price(), Some(16500.0));
+        let mut other = p.clone().with_meta("other");
+        other += (17000.0, 1.0);
+        assert_eq!(merged, other);
+        let mut scaled = -(merged * 2.0);
+        scaled += (16000.0, 4.0);
+        assert_eq!(*scaled.map(|x| x as f32).meta(), "trend");
+        assert_eq!(p.with_meta(()).price(), Some(16000.0));
+    }
+
+    #[test]
+    fn positions_iter_mut() {
+        use rust_decimal_macros::dec;
+
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
+            .unwrap()
+            .prefer_reversed(true);
+        let mut p = Positions::default();
+        p += (dec!(16000), dec!(1), &btc_usdt_swap);
+        p += Reversed((dec!(16000), dec!(-1600), &btc_usd_swap));
+        let before = p.clone();
+        for (symbol, position) in p.iter_mut() {
+            assert_eq!(symbol, position.instrument().as_symbol());
+            position.convert(dec!(20000));
+        }
+        assert_eq!(p, before);
+        assert_eq!(p.get_position(&btc_usdt_swap).unwrap().value(), &dec!(4000));
+        assert_eq!(
+            p.get_position(&btc_usd_swap).unwrap().price(),
+            Some(dec!(20000))
+        );
+        assert_eq!(p.iter_mut().count(), 2);
+    }
+
+    #[test]
+    fn eval_with_spread() {
+        use rust_decimal_macros::dec;
+
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
+            .unwrap()
+            .prefer_reversed(true);
+        let btc_usdt = Symbol::spot(&Asset::btc(), &Asset::usdt());
+        let mut p = Positions::default();
+        p += (dec!(16000), dec!(1), &btc_usdt_swap);
+        p += Reversed((dec!(16000), dec!(-16000), &btc_usd_swap));
+        p += (dec!(2), &Asset::btc());
+        let bids = HashMap::from([
+            (btc_usdt_swap.as_symbol().clone(), dec!(15990)),
+            (btc_usd_swap.as_symbol().clone(), dec!(15990)),
+            (btc_usdt.clone(), dec!(15990)),
+        ]);
+        let asks = HashMap::from([
+            (btc_usdt_swap.as_symbol().clone(), dec!(16010)),
+            (btc_usd_swap.as_symbol().clone(), dec!(16000)),
+            (btc_usdt.clone(), dec!(16010)),
+        ]);
+        let usdt = Asset::usdt();
+        let expr = p.as_expr();
+        let liquidation = expr.eval_with_spread(&usdt, &bids, &asks).unwrap();
+        // The long swap is closed at the bid, the short inverse swap at the ask,
+        // and the resulting positive BTC value is sold at the bid.
+        assert_eq!(liquidation, dec!(-10) + dec!(2) * dec!(15990));
+        assert!(liquidation <= expr.eval(&usdt, &bids).unwrap());
+        assert!(liquidation <= expr.eval(&usdt, &asks).unwrap());
+        let mut missing = asks.clone();
+        missing.remove(btc_usd_swap.as_symbol());
+        assert!(expr.eval_with_spread(&usdt, &bids, &missing).is_none());
+    }
+
+    #[test]
+    fn flatten_base() {
+        use rust_decimal::Decimal;
+        use rust_decimal_macros::dec;
+
+        let btc_usdt = Instrument::spot(&Asset::btc(), &Asset::usdt());
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let btc_busd_swap = Instrument::try_new(
+            "SWAP:BTC-BUSD-SWAP",
+            &Asset::btc(),
+            &Asset::from_str("BUSD").unwrap(),
+        )
+        .unwrap();
+        let mut p = Positions::<Decimal>::default();
+        p += (dec!(16000), dec!(3), &btc_usdt);
+        p += (dec!(16000), dec!(2), &btc_busd_swap);
+        p += (dec!(16000), dec!(-4), &btc_usdt_swap);
+        let mut prices = HashMap::from([
+            (btc_busd_swap.as_symbol().clone(), dec!(20000)),
+            (btc_usdt_swap.as_symbol().clone(), dec!(20000)),
+        ]);
+        let before = p.clone();
+        assert!(p.flatten_base(&Asset::btc(), &prices).is_none());
+        assert_eq!(p, before);
+        prices.insert(btc_usdt.as_symbol().clone(), dec!(20000));
+        let realized = p.flatten_base(&Asset::btc(), &prices).unwrap();
+        assert_eq!(realized, dec!(0.2));
+        assert_eq!(p.get_position(&btc_usdt).unwrap().size(), dec!(2));
+        assert_eq!(p.get_position(&btc_busd_swap).unwrap().size(), dec!(2));
+        assert_eq!(p.get_position(&btc_usdt_swap).unwrap().size(), dec!(-4));
+        assert_eq!(p.flatten_base(&Asset::btc(), &prices), Some(dec!(0)));
+    }
+
+    #[test]
+    fn reconcile() {
+        use rust_decimal::Decimal;
+        use rust_decimal_macros::dec;
+
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let mut p = Positions::<Decimal>::default();
+        p += (dec!(1000), &Asset::usdt());
+        p += (dec!(1), &Asset::btc());
+        p += (dec!(16000), dec!(1), dec!(-2.5), &btc_usdt_swap);
+        let balances = HashMap::from([
+            (Asset::usdt(), dec!(997.5)),
+            (Asset::btc(), dec!(1.1)),
+            (Asset::eth(), dec!(0.000001)),
+        ]);
+        assert!(p.reconcile(&balances, &dec!(0.2)).is_empty());
+        let discrepancies = p.reconcile(&balances, &dec!(0.00001));
+        assert_eq!(
+            discrepancies,
+            [Discrepancy {
+                asset: Asset::btc(),
+                expected: dec!(1.1),
+                actual: dec!(1),
+                delta: dec!(-0.1),
+            }]
+        );
+        assert_eq!(p.reconcile(&balances, &dec!(0)).len(), 2);
+
+        let eth_usd_quanto =
+            Instrument::derivative("FUTURES", "ETH-USD-QUANTO", &Asset::eth(), &Asset::btc())
+                .unwrap()
+                .quanto(&Asset::usd());
+        let mut p = Asset::btc().value(dec!(1));
+        p += (dec!(1000), dec!(2), &eth_usd_quanto);
+        p += (dec!(1100), dec!(-2), &eth_usd_quanto);
+        let balances = HashMap::from([(Asset::btc(), dec!(1)), (Asset::usd(), dec!(200))]);
+        assert!(p.reconcile(&balances, &dec!(0)).is_empty());
+    }
+
+    #[test]
+    fn leverage() {
+        let btc = Asset::btc();
+        let usdt = Asset::usdt();
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
+            .unwrap()
+            .prefer_reversed(true);
+        let mut p = Positions::default();
+        p += (Decimal::from(1000), &usdt);
+        p += (Decimal::from(20000), Decimal::from(-0.1), &btc_usdt_swap);
+        p += Reversed((Decimal::from(20000), Decimal::from(2000), &btc_usd_swap));
+        let mut prices = HashMap::from([
+            (btc_usdt_swap.as_symbol().clone(), Decimal::from(20000)),
+            (btc_usd_swap.as_symbol().clone(), Decimal::from(20000)),
+        ]);
+        assert!(p.leverage(&usdt, &prices).is_none());
+        prices.insert(Symbol::spot(&btc, &usdt), Decimal::from(20000));
+        assert_eq!(
+            p.gross_notional(&usdt, &prices).unwrap(),
+            Decimal::from(4000)
+        );
+        assert_eq!(p.leverage(&usdt, &prices).unwrap(), Decimal::from(4));
+        assert!(Positions::<Decimal>::default()
+            .leverage(&usdt, &prices)
+            .is_none());
+    }
+
+    #[test]
+    fn display_tree() {
+        #[cfg(not(feature = "std"))]
+        use alloc::string::ToString;
+
+        let btc = Asset::btc();
+        let usdt = Asset::usdt();
+        let eth_btc_swap =
+            Instrument::try_new("SWAP:ETH-BTC-SWAP", &Asset::eth(), &Asset::btc()).unwrap();
+        let mut p = Positions::default();
+        p += (-16000, &usdt);
+        p += (1, &btc);
+        p += (2, -21, &eth_btc_swap);
+        let s = p.as_tree(&usdt).display_tree().to_string();
+        #[cfg(feature = "std")]
+        println!("{s}");
+        assert_eq!(
+            s,
+            "USDT => -16000 USDT\n    [BTC-USDT]\n        BTC => 1 BTC\n            SWAP:ETH-BTC-SWAP => (2, -21 ETH)\n"
+        );
+    }
+
+    #[test]
+    fn tree_gross_net() {
+        use rust_decimal_macros::dec;
+
+        let btc = Asset::btc();
+        let usdt = Asset::usdt();
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let eth_btc_swap =
+            Instrument::try_new("SWAP:ETH-BTC-SWAP", &Asset::eth(), &Asset::btc()).unwrap();
+        let mut p = Positions::default();
+        p += (dec!(100), &usdt);
+        p += (dec!(1), &btc);
+        p += (dec!(0.06), dec!(-10), &eth_btc_swap);
+        p += (dec!(16000), dec!(1), &btc_usdt_swap);
+        let tree = p.as_tree(&usdt);
+        let prices = HashMap::from([
+            (eth_btc_swap, dec!(0.07)),
+            (btc_usdt_swap, dec!(17000)),
+            (Instrument::from((btc, usdt.clone())), dec!(20000)),
+        ]);
+        let (gross, net) = tree.eval_gross_net(&prices).unwrap();
+        assert_eq!(net, tree.eval(&prices).unwrap());
+        assert_eq!(net, dec!(100) + dec!(1000) + dec!(0.9) * dec!(20000));
+        assert_eq!(gross, dec!(100) + dec!(1000) + dec!(1.1) * dec!(20000));
+        let symbols: HashMap<Symbol, _> = prices
+            .iter()
+            .map(|(inst, price)| (inst.as_symbol().clone(), *price))
+            .collect();
+        assert_eq!(tree.eval_gross_net(&symbols), Some((gross, net)));
+        assert!(tree
+            .eval_gross_net(&HashMap::<Symbol, rust_decimal::Decimal>::default())
+            .is_none());
+    }
+
+    #[test]
+    fn remove_from_tree() {
+        let btc = Asset::btc();
+        let usdt = Asset::usdt();
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let eth_btc_swap =
+            Instrument::try_new("SWAP:ETH-BTC-SWAP", &Asset::eth(), &Asset::btc()).unwrap();
+        let mut p = Positions::default();
+        p += (Decimal::from(-16000), &usdt);
+        p += (Decimal::from(1), &btc);
+        p += (Decimal::from(0.067), Decimal::from(-21.5), &eth_btc_swap);
+        p += (Decimal::from(16001), Decimal::from(-1.5), &btc_usdt_swap);
+        let mut tree = p.as_tree(&usdt);
+        assert!(tree.remove_position(&btc_usdt_swap).is_some());
+        assert!(tree.remove_position(&btc_usdt_swap).is_none());
+        let prices = HashMap::from([
+            (eth_btc_swap.clone(), Decimal::from(0.059)),
+            (
+                Instrument::from((btc.clone(), usdt.clone())),
+                Decimal::from(17000),
+            ),
+        ]);
+        assert_eq!(
+            tree.eval(&prices).unwrap().set_precision(1),
+            Decimal::from(3924).set_precision(1)
+        );
+        let child = tree.remove_child(&btc).unwrap();
+        assert_eq!(child.instruments().count(), 1);
+        assert!(tree.remove_child(&btc).is_none());
+        assert_eq!(tree.eval(&prices).unwrap(), Decimal::from(-16000));
+    }
+
+    #[test]
+    fn instruments_of_expr() {
+        #[cfg(not(feature = "std"))]
+        use alloc::vec::Vec;
+
+        let btc = Asset::btc();
+        let usdt = Asset::usdt();
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
+            .unwrap()
+            .prefer_reversed(true);
+        let eth_btc_swap =
+            Instrument::try_new("SWAP:ETH-BTC-SWAP", &Asset::eth(), &Asset::btc()).unwrap();
+        let mut p = Positions::default();
+        p += (Decimal::from(-16000), &usdt);
+        p += (Decimal::from(1), &btc);
+        p += Reversed((Decimal::from(16000), Decimal::from(-16000), &btc_usd_swap));
+        p += (Decimal::from(0.067), Decimal::from(-21.5), &eth_btc_swap);
+        p += (
+            Decimal::from(16001),
+            Decimal::from(-1.5),
+            Decimal::from(-2.7),
+            &btc_usdt_swap,
+        );
+        let insts = p.as_expr().instruments(&Asset::ETH).collect::<Vec<_>>();
+        let usdt_eth = Instrument::spot(&Asset::USDT, &Asset::ETH);
+        let btc_eth = Instrument::spot(&Asset::BTC, &Asset::ETH);
+        assert_eq!(insts.len(), 5);
+        for inst in [
+            &btc_usd_swap,
+            &btc_usdt_swap,
+            &eth_btc_swap,
+            &usdt_eth,
+            &btc_eth,
+        ] {
+            assert!(insts.contains(inst));
+        }
+    }
+
+    #[test]
+    fn eval_expr() {
+        let btc = Asset::btc();
+        let usdt = Asset::usdt();
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
+            .unwrap()
+            .prefer_reversed(true);
+        let eth_btc_swap =
+            Instrument::try_new("SWAP:ETH-BTC-SWAP", &Asset::eth(), &Asset::btc()).unwrap();
+        let mut p = Positions::default();
+        #[cfg(feature = "std")]
+        println!("{}", p.as_expr());
+        p += (Decimal::from(-16000), &usdt);
+        #[cfg(feature = "std")]
+        println!("{}", p.as_expr());
+        p += (Decimal::from(1), &btc);
+        #[cfg(feature = "std")]
+        println!("{}", p.as_expr());
+        p += Reversed((Decimal::from(16000), Decimal::from(-16000), &btc_usd_swap));
+        #[cfg(feature = "std")]
+        println!("{}", p.as_expr());
+        p += (Decimal::from(0.067), Decimal::from(-21.5), &eth_btc_swap);
+        #[cfg(feature = "std")]
+        println!("{}", p.as_expr());
+        p += (
+            Decimal::from(16001),
+            Decimal::from(-1.5),
+            Decimal::from(-2.7),
+            &btc_usdt_swap,
+        );
+        let expr = p.as_expr();
+        #[cfg(feature = "std")]
+        println!("{}", expr);
+        let prices = HashMap::from([
+            (eth_btc_swap.as_symbol().clone(), Decimal::from(0.059)),
+            (btc_usd_swap.as_symbol().clone(), Decimal::from(17000)),
+            (btc_usdt_swap.as_symbol().clone(), Decimal::from(17002)),
+            (Symbol::spot(&btc, &usdt), Decimal::from(17000)),
+        ]);
+        #[cfg(feature = "std")]
+        for inst in expr.instruments(&Asset::USDT) {
+            println!("{inst}");
+        }
+        let ans = expr.eval(&Asset::USDT, &prices).unwrap().set_precision(1);
+        #[cfg(feature = "std")]
+        println!("{ans}");
+        assert_eq!(ans, Decimal::from(1419.8).set_precision(1));
+        let compiled = expr.compile(&Asset::USDT);
+        assert_eq!(compiled.instruments().len(), 4);
+        assert_eq!(compiled.eval(&prices).unwrap().set_precision(1), ans);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn eval_fresh() {
+        use std::time::{Duration, Instant};
+
+        let btc = Asset::btc();
+        let usdt = Asset::usdt();
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let mut p = Positions::default();
+        p += (Decimal::from(-16000), &usdt);
+        p += (Decimal::from(1), &btc);
+        p += (Decimal::from(16000), Decimal::from(-1), &btc_usdt_swap);
+        p += (
+            Decimal::from(16000),
+            Decimal::from(1),
+            &Instrument::spot(&btc, &usdt),
+        );
+        let now = Instant::now();
+        let mut prices = HashMap::from([(
+            btc_usdt_swap.as_symbol().clone(),
+            (Decimal::from(17000), now),
+        )]);
+        let max_age = Duration::from_secs(60);
+        assert!(matches!(
+            p.eval_fresh(&usdt, &prices, max_age),
+            Err(StaleError::Missing(symbol)) if symbol == Symbol::spot(&btc, &usdt)
+        ));
+        let Some(old) = now.checked_sub(Duration::from_secs(120)) else {
+            return;
+        };
+        prices.insert(Symbol::spot(&btc, &usdt), (Decimal::from(17000), old));
+        let err = p.eval_fresh(&usdt, &prices, max_age).unwrap_err();
+        assert_eq!(err.to_string(), "stale prices of BTC-USDT");
+        prices.insert(Symbol::spot(&btc, &usdt), (Decimal::from(17000), now));
+        assert_eq!(
+            p.eval_fresh(&usdt, &prices, max_age).unwrap(),
+            Decimal::from(1000)
+        );
+    }
+
+    #[test]
+    fn equity_components() {
+        let btc = Asset::btc();
+        let usdt = Asset::usdt();
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
+            .unwrap()
+            .prefer_reversed(true);
+        let eth_btc_swap =
+            Instrument::try_new("SWAP:ETH-BTC-SWAP", &Asset::eth(), &Asset::btc()).unwrap();
+        let mut p = Positions::default();
+        p += (Decimal::from(-16000), &usdt);
+        p += (Decimal::from(1), &btc);
+        p += Reversed((Decimal::from(16000), Decimal::from(-16000), &btc_usd_swap));
+        p += (Decimal::from(0.067), Decimal::from(-21.5), &eth_btc_swap);
+        p += (
+            Decimal::from(16001),
+            Decimal::from(-1.5),
+            Decimal::from(-2.7),
+            &btc_usdt_swap,
+        );
+        let prices = HashMap::from([
+            (eth_btc_swap.as_symbol().clone(), Decimal::from(0.059)),
+            (btc_usd_swap.as_symbol().clone(), Decimal::from(17000)),
+            (btc_usdt_swap.as_symbol().clone(), Decimal::from(17002)),
+            (Symbol::spot(&btc, &usdt), Decimal::from(17000)),
+        ]);
+        let (cash, positions) = p.snapshot_equity_components(&usdt, &prices).unwrap();
+        assert_eq!(cash, Decimal::from(1000));
+        assert_eq!(p.cash_in(&usdt, &prices), Some(cash.clone()));
+        assert!(p.cash_in(&Asset::ETH, &prices).is_none());
+        assert_eq!(
+            p.cash_in(&btc, &prices),
+            Some(cash.clone() / Decimal::from(17000))
+        );
+        assert_eq!(
+            (cash + positions).set_precision(1),
+            p.as_expr().eval(&usdt, &prices).unwrap().set_precision(1)
+        );
+        assert!(p.snapshot_equity_components(&Asset::ETH, &prices).is_none());
+    }
+
+    #[test]
+    fn eval_quanto() {
+        let btc = Asset::btc();
+        let usd = Asset::usd();
+        let eth_usd_quanto =
+            Instrument::derivative("FUTURES", "ETH-USD-QUANTO", &Asset::eth(), &btc)
+                .unwrap()
+                .quanto(&usd);
+        let p = eth_usd_quanto.position((Decimal::from(1000), Decimal::from(2)));
+        assert_eq!(
+            p.closed_with_rate(&Decimal::from(1100), &Decimal::from(0.00005)),
+            Decimal::from(0.01)
+        );
+        let p = Positions::from(p);
+        let insts = p
+            .as_expr()
+            .instruments(&btc)
+            .collect::<alloc::vec::Vec<_>>();
+        assert_eq!(insts.len(), 2);
+        assert!(insts.contains(&Instrument::spot(&usd, &btc)));
+        let mut prices = HashMap::from([(eth_usd_quanto.as_symbol().clone(), Decimal::from(1100))]);
+        assert!(p.as_expr().eval(&btc, &prices).is_none());
+        prices.insert(Symbol::spot(&usd, &btc), Decimal::from(0.00005));
+        assert_eq!(
+            p.as_expr().eval(&btc, &prices).unwrap(),
+            Decimal::from(0.01)
+        );
+    }
+
+    #[test]
+    fn concentrate_quanto() {
+        let btc = Asset::btc();
+        let usd = Asset::usd();
+        let eth_usd_quanto =
+            Instrument::derivative("FUTURES", "ETH-USD-QUANTO", &Asset::eth(), &btc)
+                .unwrap()
+                .quanto(&usd);
+        let mut p = btc.value(Decimal::from(1));
+        p += (Decimal::from(1000), Decimal::from(2), &eth_usd_quanto);
+        p += (Decimal::from(1100), Decimal::from(-2), &eth_usd_quanto);
+        let prices = HashMap::from([
+            (eth_usd_quanto.as_symbol().clone(), Decimal::from(1100)),
+            (Symbol::spot(&usd, &btc), Decimal::from(0.00005)),
+        ]);
+        let equity = p.as_expr().eval(&btc, &prices).unwrap();
+        assert_eq!(equity, Decimal::from(1.01));
+        p.concentrate();
+        assert_eq!(p.get_value(&btc), Some(&Decimal::from(1)));
+        assert_eq!(
+            p.get_position(&eth_usd_quanto).unwrap().value(),
+            &Decimal::from(200)
+        );
+        assert_eq!(p.as_expr().eval(&btc, &prices).unwrap(), equity);
+    }
+
+    #[test]
+    fn extract_positions() {
+        let usdt = Asset::usdt();
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let btc_usdt_futures =
+            Instrument::try_new("FUTURES:BTC-USDT-221230", &Asset::btc(), &Asset::usdt()).unwrap();
+        let btc_usd_futures =
+            Instrument::try_new("FUTURES:BTC-USD-221230", &Asset::usd(), &Asset::btc())
+                .unwrap()
+                .prefer_reversed(true);
+        let mut p = Positions::default();
+        p += (Decimal::from(100), &usdt);
+        p += (Decimal::from(16000), Decimal::from(1), &btc_usdt_swap);
+        p += (Decimal::from(16100), Decimal::from(-1), &btc_usdt_futures);
+        p += Reversed((Decimal::from(16000), Decimal::from(100), &btc_usd_futures));
+        let expired = p.extract(|inst, _| inst.as_symbol().derivative_prefix() == Some("FUTURES"));
+        assert_eq!(expired.len(), 2);
+        assert!(expired.get_position(&btc_usdt_futures).is_some());
+        assert!(expired.get_position(&btc_usd_futures).is_some());
+        assert_eq!(expired.get_value(&usdt), Some(&Decimal::from(0)));
+        assert_eq!(p.len(), 1);
+        assert!(p.get_position(&btc_usdt_swap).is_some());
+        assert!(p.get_position(&btc_usdt_futures).is_none());
+        assert_eq!(p.get_value(&usdt), Some(&Decimal::from(100)));
+    }
+
+    #[test]
+    fn add_positions() {
+        let inst = Instrument::from((Asset::btc(), Asset::usdt()));
+        let lhs = inst.position((Decimal::from(16000), Decimal::from(1)));
+        let rhs = inst.position((Decimal::from(15000), Decimal::from(1)));
+        assert_eq!(
+            lhs.clone() + &rhs,
+            inst.position((Decimal::from(15500), Decimal::from(2)))
+        );
+        assert_eq!(
+            lhs + -rhs,
+            inst.position((Decimal::from(16000), Decimal::from(0), Decimal::from(-1000)))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot add positions of different instruments")]
+    fn add_positions_of_different_instruments() {
+        let btc_usdt = Instrument::from((Asset::btc(), Asset::usdt()));
+        let eth_usdt = Instrument::from((Asset::eth(), Asset::usdt()));
+        let _ = btc_usdt.position((Decimal::from(16000), Decimal::from(1)))
+            + eth_usdt.position((Decimal::from(1200), Decimal::from(1)));
+    }
+
+    #[test]
+    fn capacity() {
+        let mut p = Positions::<Decimal>::with_capacity(8);
+        assert!(p.capacity() >= 8);
+        assert!(p.is_empty());
+        p.reserve(32);
+        assert!(p.capacity() >= 32);
+    }
+
+    #[test]
+    fn entry_position() {
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let mut p = Positions::default();
+        assert!(p.entry_position(&btc_usdt_swap).is_zero());
+        *p.entry_position(&btc_usdt_swap) += (Decimal::from(16000), Decimal::from(1));
+        *p.entry_position(&btc_usdt_swap) += (Decimal::from(15000), Decimal::from(1));
+        assert_eq!(
+            p.get_position(&btc_usdt_swap).unwrap(),
+            &btc_usdt_swap.position((Decimal::from(15500), Decimal::from(2)))
+        );
+    }
+
+    #[test]
+    fn position_to_naive() {
+        use crate::ToNaivePosition;
+
+        let inst = Instrument::from((Asset::btc(), Asset::usdt()));
+        let p = inst.position((Decimal::from(16000), Decimal::from(1)));
+        assert_eq!(p.to_naive(), ToNaivePosition::to_naive(&&p));
+        assert_eq!(p.to_naive(), (Decimal::from(16000), Decimal::from(1)));
+    }
+
+    #[test]
+    fn assets() {
+        let btc = Asset::btc();
+        let usdt = Asset::usdt();
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
+            .unwrap()
+            .prefer_reversed(true);
+        let mut p = Positions::default();
+        p += (Decimal::from(-16000), &usdt);
+        p += (Decimal::from(1), &btc);
+        p += Reversed((Decimal::from(16000), Decimal::from(-16000), &btc_usd_swap));
+        p += (Decimal::from(16001), Decimal::from(-1.5), &btc_usdt_swap);
+        let assets = p.assets().collect::<HashSet<_>>();
+        assert_eq!(assets.len(), 3);
+        for asset in [&btc, &usdt, &Asset::USD] {
+            assert!(assets.contains(asset));
+        }
+    }
+
+    #[test]
+    fn total_size_of_base() {
+        let btc = Asset::btc();
+        let usdt = Asset::usdt();
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let eth_btc_swap =
+            Instrument::try_new("SWAP:ETH-BTC-SWAP", &Asset::eth(), &Asset::btc()).unwrap();
+        let mut p = Positions::default();
+        p += (Decimal::from(-16000), &usdt);
+        p += (Decimal::from(1), &btc);
+        p += (Decimal::from(0.067), Decimal::from(-21.5), &eth_btc_swap);
+        p += (Decimal::from(16001), Decimal::from(-1.5), &btc_usdt_swap);
+        assert_eq!(p.total_size_of_base(&btc), Decimal::from(-0.5));
+        assert_eq!(p.total_size_of_base(&Asset::eth()), Decimal::from(-21.5));
+        assert_eq!(p.total_size_of_base(&usdt), Decimal::from(-16000));
+        assert_eq!(p.total_size_of_base(&Asset::usd()), Decimal::from(0));
+    }
+
+    #[test]
+    fn try_notional_value() {
+        let inst = Instrument::from((Asset::btc(), Asset::usdt()));
+        let p = inst.position((16000i32, -2));
+        assert_eq!(p.try_notional_value(), Some(-32000));
+        let p = inst.position((i32::MAX, 2));
+        assert_eq!(p.try_notional_value(), None);
+    }
+
+    #[test]
+    fn average_with() {
+        let inst = Instrument::from((Asset::btc(), Asset::usdt()));
+        let p = inst.position((Decimal::from(16000), Decimal::from(1.5)));
+        let q = p.average_with(&Decimal::from(15000), &Decimal::from(1.5));
+        assert_eq!(q, inst.position((Decimal::from(15500), Decimal::from(3))));
+        assert_eq!(p, inst.position((Decimal::from(16000), Decimal::from(1.5))));
+
+        let inst = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
+            .unwrap()
+            .prefer_reversed(true);
+        let mut p = inst.position(Reversed((Decimal::from(16000), Decimal::from(100))));
+        let q = p.average_with(&Decimal::from(15000), &Decimal::from(100));
+        p += Reversed((Decimal::from(15000), Decimal::from(100)));
+        assert_eq!(p, q);
+        assert_eq!(q.size(), Decimal::from(200));
+    }
+
+    #[test]
+    fn apply_split() {
+        use rust_decimal_macros::dec;
+
+        let usdt = Asset::usdt();
+        let ton: Asset = "TON".parse().unwrap();
+        let ton_usdt = Instrument::spot(&ton, &usdt);
+        let btc_ton = Instrument::spot(&Asset::btc(), &ton);
+        let mut p = Positions::default();
+        p += (dec!(5), &ton);
+        p += (dec!(2), dec!(100), &ton_usdt);
+        p += (dec!(8000), dec!(0.01), dec!(1), &btc_ton);
+        let notional = p.get_position(&ton_usdt).unwrap().notional_value();
+        p.apply_split(&ton, &dec!(10));
+        assert_eq!(p.get_value(&ton), Some(&dec!(50)));
+        let q = p.get_position(&ton_usdt).unwrap();
+        assert_eq!(q, &ton_usdt.position((dec!(0.2), dec!(1000))));
+        assert_eq!(q.notional_value(), notional);
+        assert_eq!(
+            p.get_position(&btc_ton).unwrap(),
+            &btc_ton.position((dec!(80000), dec!(0.01), dec!(10)))
+        );
+
+        let eth_ton_quanto =
+            Instrument::derivative("FUTURES", "ETH-TON-QUANTO", &Asset::eth(), &Asset::btc())
+                .unwrap()
+                .quanto(&ton);
+        let mut p = Positions::default();
+        p += (dec!(1000), dec!(2), dec!(3), &eth_ton_quanto);
+        let prices = HashMap::from([
+            (eth_ton_quanto.as_symbol().clone(), dec!(1100)),
+            (Symbol::spot(&ton, &Asset::btc()), dec!(0.0001)),
+        ]);
+        let equity = p.as_expr().eval(&Asset::btc(), &prices).unwrap();
+        p.apply_split(&ton, &dec!(10));
+        assert_eq!(
+            p.get_position(&eth_ton_quanto).unwrap(),
+            &eth_ton_quanto.position((dec!(10000), dec!(2), dec!(30)))
+        );
+        let prices = HashMap::from([
+            (eth_ton_quanto.as_symbol().clone(), dec!(11000)),
+            (Symbol::spot(&ton, &Asset::btc()), dec!(0.00001)),
+        ]);
+        assert_eq!(p.as_expr().eval(&Asset::btc(), &prices), Some(equity));
+        p.apply_split(&Asset::btc(), &dec!(2));
+        assert_eq!(
+            p.get_position(&eth_ton_quanto).unwrap(),
+            &eth_ton_quanto.position((dec!(10000), dec!(2), dec!(30)))
+        );
+    }
+
+    #[test]
+    fn rename_asset() {
+        let usdt = Asset::usdt();
+        let usdc: Asset = "USDC".parse().unwrap();
+        let ust: Asset = "UST".parse().unwrap();
+        let ust_usdt = Instrument::spot(&ust, &usdt);
+        let btc_ust_swap = Instrument::try_new("SWAP:BTC-UST-SWAP", &Asset::btc(), &ust).unwrap();
+        let mut p = Positions::default();
+        p += (Decimal::from(100), &usdc);
+        p += (Decimal::from(50), &ust);
+        p += (Decimal::from(1), Decimal::from(20), &ust_usdt);
+        p += (Decimal::from(16000), Decimal::from(1), &btc_ust_swap);
+        p.rename_asset(&ust, &usdc);
+        assert!(p.get_value(&ust).is_none());
+        assert_eq!(p.get_value(&usdc), Some(&Decimal::from(150)));
+        let usdc_usdt = Instrument::spot(&usdc, &usdt);
+        assert_eq!(
+            p.get_position(&usdc_usdt).unwrap(),
+            &usdc_usdt.position((Decimal::from(1), Decimal::from(20)))
+        );
+        assert!(p.get_position(&ust_usdt).is_none());
+        let swap = p
+            .get_position(&Instrument::try_new("SWAP:BTC-UST-SWAP", &Asset::btc(), &usdc).unwrap())
+            .unwrap();
+        assert_eq!(swap.instrument().quote(), &usdc);
+    }
+
+    #[test]
+    fn value_series() {
+        let marks = [
+            Decimal::from(15000),
+            Decimal::from(16000),
+            Decimal::from(17500),
+        ];
+        let inst = Instrument::from((Asset::btc(), Asset::usdt()));
+        let p = inst.position((Decimal::from(16000), Decimal::from(-1.5), Decimal::from(3)));
+        let series = p.value_series(&marks);
+        assert_eq!(series[0], Decimal::from(1503));
+        for (mark, value) in marks.iter().zip(series) {
+            assert_eq!(p.closed(mark), value);
+        }
+        let inst = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
+            .unwrap()
+            .prefer_reversed(true);
+        let p = inst.position(Reversed((Decimal::from(16000), Decimal::from(100))));
+        for (mark, value) in marks.iter().zip(p.value_series(&marks)) {
+            assert_eq!(p.closed(mark), value);
+        }
+    }
+
+    #[test]
+    fn as_naive_mut() {
+        let inst = Instrument::from((Asset::btc(), Asset::usdt()));
+        let mut p = inst.position((Decimal::from(16000), Decimal::from(1), Decimal::from(3)));
+        p.as_naive_mut().value = Decimal::from(0);
+        assert_eq!(p, inst.position((Decimal::from(16000), Decimal::from(1))));
+    }
 
-    type Decimal = GenericDecimal<BigInt, usize>;
+    #[test]
+    fn zero_position() {
+        let inst = Instrument::from((Asset::btc(), Asset::usdt()));
+        let mut p = Position::zero(&inst);
+        assert!(p.is_zero());
+        assert_eq!(p, inst.position(Decimal::from(0)));
+        p += (Decimal::from(16000), Decimal::from(1));
+        assert!(!p.is_zero());
+    }
 
     #[test]
-    fn normal() {
-        let mut p = Position::new(
-            Instrument::from((Asset::btc(), Asset::usdt())),
-            Decimal::zero(),
-        );
-        p += Decimal::from(1.5);
-        p += (Decimal::from(2.3), Decimal::from(2.5));
-        p += (Decimal::from(7.3), Decimal::from(3.4));
-        p += (Decimal::from(3.7), Decimal::from(-7.8), Decimal::from(12));
-        #[cfg(feature = "std")]
-        println!("{p}");
-        assert_eq!(
-            p,
-            Position::new(
-                Instrument::from((Asset::btc(), Asset::usdt())),
-                (Decimal::from(3.7), Decimal::from(-1.9), Decimal::from(4.7),)
-            )
-        );
-        #[cfg(feature = "std")]
-        println!("{}", p.as_tree());
+    fn from_display_parts() {
+        let inst = Instrument::from((Asset::btc(), Asset::usdt()));
+        let p = Position::from_display_parts(inst.clone(), Decimal::from(16000), Decimal::from(1));
+        assert_eq!(p, inst.position((Decimal::from(16000), Decimal::from(1))));
+        let inst = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
+            .unwrap()
+            .prefer_reversed(true);
+        let p =
+            Position::from_display_parts(inst.clone(), Decimal::from(16000), Decimal::from(100));
+        assert_eq!(p.price(), Some(Decimal::from(16000)));
+        assert_eq!(p.size(), Decimal::from(100));
+        assert_eq!(p.as_naive().size, Decimal::from(-100));
     }
 
     #[test]
-    fn reversed() {
-        let mut p = Position::new(
-            Instrument::from((Asset::usdt(), Asset::btc())).prefer_reversed(true),
-            Decimal::zero(),
-        );
-        p += Reversed(Decimal::from(1.5));
-        p += Reversed((Decimal::from(3), Decimal::from(2)));
-        p += Reversed((Decimal::from(4), Decimal::from(1)));
-        p += Reversed((Decimal::from(2), Decimal::from(-7), Decimal::from(-1.4)));
-        #[cfg(feature = "std")]
-        println!("{p}");
+    fn scale_position() {
+        use rust_decimal_macros::dec;
+
+        let inst = Instrument::from((Asset::btc(), Asset::usdt()));
+        let p = inst.position((dec!(16000), dec!(-1.5), dec!(3)));
         assert_eq!(
-            *p.value(),
-            (Decimal::from(-29) / Decimal::from(60)).set_precision(1),
+            p.clone() * dec!(0.5),
+            inst.position((dec!(16000), dec!(-0.75), dec!(1.5)))
         );
-        #[cfg(feature = "std")]
-        println!("{}", p.as_tree());
+        let mut q = p;
+        q *= dec!(2);
+        assert_eq!(q, inst.position((dec!(16000), dec!(-3), dec!(6))));
+        let inst = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
+            .unwrap()
+            .prefer_reversed(true);
+        let p = inst.position(Reversed((dec!(16000), dec!(100)))) * dec!(0.5);
+        assert_eq!(p.price(), Some(dec!(16000)));
+        assert_eq!(p.size(), dec!(50));
     }
 
     #[test]
-    fn basic_positions() {
-        let btc = Asset::btc();
-        let usdt = Asset::usdt();
+    fn positions_from_str() {
+        use rust_decimal::Decimal;
+        use rust_decimal_macros::dec;
+
         let btc_usdt_swap =
             Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
         let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
             .unwrap()
             .prefer_reversed(true);
-        let eth_btc_swap =
-            Instrument::try_new("SWAP:ETH-BTC-SWAP", &Asset::eth(), &Asset::btc()).unwrap();
-        let mut p = Positions::default();
-        p += (Decimal::from(-16000), &usdt);
-        p += (Decimal::from(1), &btc);
-        p += Reversed((Decimal::from(16000), Decimal::from(-16000), &btc_usd_swap));
-        p += (Decimal::from(0.067), Decimal::from(-21.5), &eth_btc_swap);
-        p += (
-            Decimal::from(16001),
-            Decimal::from(-1.5),
-            Decimal::from(-2.7),
-            &btc_usdt_swap,
+        let p: Positions<Decimal> = "BTC:1; USDT:-16000; SWAP:BTC-USDT-SWAP@16001:-1.5:-2.7; \
+             SWAP:BTC-USD-SWAP(USD/BTC)*@16000:-16000; ETH-USDT@1200:1"
+            .parse()
+            .unwrap();
+        let mut q = Positions::default();
+        q += (dec!(1), &Asset::btc());
+        q += (dec!(-16000), &Asset::usdt());
+        q += (dec!(16001), dec!(-1.5), dec!(-2.7), &btc_usdt_swap);
+        q += Reversed((dec!(16000), dec!(-16000), &btc_usd_swap));
+        q += (
+            dec!(1200),
+            dec!(1),
+            &Instrument::spot(&Asset::eth(), &Asset::usdt()),
         );
-        #[cfg(feature = "std")]
-        println!("{p}");
+        assert_eq!(p, q);
+        let inst = p.get_position(&btc_usdt_swap).unwrap().instrument();
+        assert_eq!(inst.base(), &Asset::btc());
+        assert!(p
+            .get_position(&btc_usd_swap)
+            .unwrap()
+            .instrument()
+            .is_prefer_reversed());
+        assert!(matches!(
+            "BTC:abc".parse::<Positions<Decimal>>(),
+            Err(ParsePositionsError::InvalidNumber(_))
+        ));
+        assert!(matches!(
+            "BTC".parse::<Positions<Decimal>>(),
+            Err(ParsePositionsError::InvalidSegment(_))
+        ));
+        assert!(matches!(
+            "SWAP:BTC-USDT-SWAP@1".parse::<Positions<Decimal>>(),
+            Err(ParsePositionsError::InvalidSegment(_))
+        ));
     }
 
     #[test]
-    fn positions_as_tree() {
-        let btc = Asset::btc();
-        let usdt = Asset::usdt();
+    fn group_by_prefix() {
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let btc_usdt_futures =
+            Instrument::try_new("FUTURES:BTC-USDT-221230", &Asset::btc(), &Asset::usdt()).unwrap();
+        let btc_usdt = Instrument::spot(&Asset::btc(), &Asset::usdt());
+        let mut p = Positions::<Decimal>::default();
+        p += (Decimal::from(100), &Asset::usdt());
+        p += (Decimal::from(16000), Decimal::from(1), &btc_usdt);
+        p += (Decimal::from(16000), Decimal::from(-1), &btc_usdt_swap);
+        p += (Decimal::from(16100), Decimal::from(2), &btc_usdt_futures);
+        let groups = p.group_by_prefix();
+        assert_eq!(groups.len(), 3);
+        let spot = &groups[Symbol::SPOT_PREFIX.as_str()];
+        assert_eq!(spot.get_value(&Asset::usdt()), Some(&Decimal::from(100)));
+        assert!(spot.get_position(&btc_usdt).is_some());
+        assert!(groups["SWAP"].get_position(&btc_usdt_swap).is_some());
+        assert!(groups["FUTURES"].get_position(&btc_usdt_futures).is_some());
+        let total = groups
+            .into_values()
+            .fold(Positions::default(), |acc, g| acc + g);
+        assert_eq!(total, p);
+    }
+
+    #[test]
+    fn reduce_by_fraction() {
+        use rust_decimal::Decimal;
+        use rust_decimal_macros::dec;
+
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let mut p = Position::new(btc_usdt_swap, (dec!(16000), dec!(-2)));
+        assert_eq!(p.reduce_by_fraction(&dec!(0.5), &dec!(15000)), dec!(1000));
+        assert_eq!(p.size(), dec!(-1));
+        assert_eq!(
+            p.reduce_by_fraction(&Decimal::ONE, &dec!(17000)),
+            dec!(-1000)
+        );
+        assert!(p.size().is_zero());
+
+        let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
+            .unwrap()
+            .prefer_reversed(true);
+        let mut p = Position::from_display_parts(btc_usd_swap, dec!(16000), dec!(3200));
+        let expected = p.closed(&dec!(20000)) / dec!(4);
+        let realized = p.reduce_by_fraction(&dec!(0.25), &dec!(20000));
+        assert_eq!(realized, expected);
+        assert!(realized.is_sign_positive());
+        assert_eq!(p.size(), dec!(2400));
+        assert_eq!(p.price(), Some(dec!(16000)));
+    }
+
+    #[test]
+    #[should_panic(expected = "the fraction must be in (0, 1]")]
+    fn reduce_by_invalid_fraction() {
         let btc_usdt_swap =
             Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let mut p = Position::new(btc_usdt_swap, (16000.0, -2.0));
+        p.reduce_by_fraction(&1.5, &15000.0);
+    }
+
+    #[test]
+    fn to_normalized() {
+        use rust_decimal_macros::dec;
+
         let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
             .unwrap()
             .prefer_reversed(true);
+        let p = Position::from_display_parts(btc_usd_swap, dec!(16000), dec!(-1600));
+        let q = p.to_normalized();
+        assert!(!q.instrument().is_prefer_reversed());
+        assert_eq!(q.instrument().base(), &Asset::usd());
+        assert_eq!(q.as_naive(), p.as_naive());
+        assert_eq!(q.price(), Some(dec!(1) / dec!(16000)));
+        assert_eq!(q.size(), dec!(1600));
+        assert_eq!(q.closed(&(dec!(1) / dec!(17000))), p.closed(&dec!(17000)));
+        assert_eq!(q.to_normalized().as_naive(), q.as_naive());
+        assert_eq!(q.price(), p.price().map(|price| dec!(1) / price));
+        assert_eq!(q.size(), -p.size());
+
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let p = Position::new(btc_usdt_swap, (dec!(16000), dec!(-2)));
+        let q = p.to_normalized();
+        assert_eq!(q.instrument(), p.instrument());
+        assert_eq!(q.price(), p.price());
+        assert_eq!(q.size(), p.size());
+    }
+
+    #[test]
+    fn prune_dust() {
+        use rust_decimal::Decimal;
+        use rust_decimal_macros::dec;
+
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let eth_usdt_swap =
+            Instrument::try_new("SWAP:ETH-USDT-SWAP", &Asset::eth(), &Asset::usdt()).unwrap();
+        let mut p = Positions::<Decimal>::default();
+        p += (dec!(100), &Asset::usdt());
+        p += (dec!(0.0000001), &Asset::btc());
+        p += (dec!(16000), dec!(1), &btc_usdt_swap);
+        p += (dec!(17000), dec!(-0.99999999), dec!(-0.5), &btc_usdt_swap);
+        p += (dec!(1200), dec!(2), dec!(-1.2), &eth_usdt_swap);
+        let closed = p.get_position(&btc_usdt_swap).unwrap().as_naive().value;
+        p.prune_dust(&dec!(0.000001), &dec!(0.000001));
+        assert!(p.get_position(&btc_usdt_swap).is_none());
+        assert_eq!(p.get_value(&Asset::usdt()), Some(&(dec!(100) + closed)));
+        assert_eq!(p.get_position(&eth_usdt_swap).unwrap().size(), dec!(2));
+        assert!(p.get_value(&Asset::btc()).is_none());
+
+        let eth_usd_quanto =
+            Instrument::derivative("FUTURES", "ETH-USD-QUANTO", &Asset::eth(), &Asset::btc())
+                .unwrap()
+                .quanto(&Asset::usd());
+        let mut p = Asset::btc().value(dec!(1));
+        p += (dec!(1000), dec!(2), &eth_usd_quanto);
+        p += (dec!(1100), dec!(-2), &eth_usd_quanto);
+        let prices = HashMap::from([
+            (eth_usd_quanto.as_symbol().clone(), dec!(1100)),
+            (Symbol::spot(&Asset::usd(), &Asset::btc()), dec!(0.00005)),
+        ]);
+        let equity = p.as_expr().eval(&Asset::btc(), &prices);
+        assert_eq!(equity, Some(dec!(1.01)));
+        p.prune_dust(&dec!(0.000001), &dec!(0.000001));
+        assert!(p.get_position(&eth_usd_quanto).is_none());
+        assert_eq!(p.get_value(&Asset::btc()), Some(&dec!(1)));
+        assert_eq!(p.get_value(&Asset::usd()), Some(&dec!(200)));
+        assert_eq!(p.as_expr().eval(&Asset::btc(), &prices), equity);
+    }
+
+    #[test]
+    fn merge_duplicate_symbols() {
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let fake = Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usd()).unwrap();
         let eth_btc_swap =
             Instrument::try_new("SWAP:ETH-BTC-SWAP", &Asset::eth(), &Asset::btc()).unwrap();
-        let mut p = Positions::default();
-        p += (Decimal::from(-16000), &usdt);
-        p += (Decimal::from(1), &btc);
-        p += Reversed((Decimal::from(16000), Decimal::from(-16000), &btc_usd_swap));
-        p += (Decimal::from(0.067), Decimal::from(-21.5), &eth_btc_swap);
-        p += (
-            Decimal::from(16001),
-            Decimal::from(-1.5),
-            Decimal::from(-2.7),
-            &btc_usdt_swap,
+        let mut p = Positions::<Decimal>::default();
+        p += (Decimal::from(16000), Decimal::from(1), &btc_usdt_swap);
+        p += (Decimal::from(16000), Decimal::from(1), &fake);
+        p += (Decimal::from(1), Decimal::from(1), &eth_btc_swap);
+        // A misplaced position, e.g. from a hand-edited snapshot.
+        p.values.entry(Asset::usdt()).or_default().positions.insert(
+            Symbol::from_str("SWAP:MISPLACED").unwrap(),
+            Position::new(eth_btc_swap.clone(), (Decimal::from(1), Decimal::from(1))),
         );
-        let tree = p.as_tree(&usdt);
-        #[cfg(feature = "std")]
-        println!("{tree}");
-        let prices = HashMap::from([
-            (eth_btc_swap.clone(), Decimal::from(0.059)),
-            (btc_usd_swap.clone(), Decimal::from(17000)),
-            (btc_usdt_swap.clone(), Decimal::from(17002)),
-            (
-                Instrument::from((btc.clone(), usdt.clone())),
-                Decimal::from(17000),
-            ),
-        ]);
-        #[cfg(feature = "std")]
-        for inst in tree.instruments() {
-            println!("{inst}");
-        }
-        let ans = tree.eval(&prices).unwrap().set_precision(1);
-        #[cfg(feature = "std")]
-        println!("{ans}");
-        assert_eq!(ans, Decimal::from(1419.8).set_precision(1));
+        let conflicts = p.merge_duplicate_symbols();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].symbol, *btc_usdt_swap.as_symbol());
+        let mut quotes = conflicts[0]
+            .instruments
+            .iter()
+            .map(|inst| inst.quote().clone())
+            .collect::<Vec<_>>();
+        quotes.sort();
+        assert_eq!(quotes, [Asset::usd(), Asset::usdt()]);
+        assert_eq!(
+            p.get_position(&btc_usdt_swap).unwrap().size(),
+            Decimal::from(1)
+        );
+        assert_eq!(p.get_position(&fake).unwrap().size(), Decimal::from(1));
+        assert_eq!(
+            p.get_position(&eth_btc_swap).unwrap().size(),
+            Decimal::from(2)
+        );
+        assert_eq!(p.values[&Asset::usdt()].len(), 1);
     }
 
     #[test]
-    fn instruments_of_expr() {
-        #[cfg(not(feature = "std"))]
-        use alloc::vec::Vec;
+    fn map_positions() {
+        use rust_decimal::Decimal;
+        use rust_decimal_macros::dec;
 
-        let btc = Asset::btc();
         let usdt = Asset::usdt();
         let btc_usdt_swap =
             Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
+        let mut p = Positions::default();
+        p += (-16000.5, &usdt);
+        p += (16001.25, -1.5, &btc_usdt_swap);
+        let p = p.map(|x| Decimal::try_from(x).unwrap());
+        assert_eq!(p.get_value(&usdt), Some(&dec!(-16000.5)));
+        assert_eq!(
+            p.get_position(&btc_usdt_swap).unwrap(),
+            &btc_usdt_swap.position((dec!(16001.25), dec!(-1.5)))
+        );
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode() -> anyhow::Result<()> {
+        let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::USD, &Asset::BTC)?
+            .prefer_reversed(true)
+            .with_lot_size("100")?;
+        let eth_usdt_quanto = Instrument::try_new("SWAP:ETH-USD-SWAP", &Asset::ETH, &Asset::USD)?
+            .quanto(&Asset::USDT);
+        let mut p = Asset::USDT.value(1000.0);
+        p += Reversed((16000.0, -100.0, 0.5, &btc_usd_swap));
+        p += (1500.0, 2.0, &eth_usdt_quanto);
+        p.charge_fee(0.25, &Asset::USDT);
+        let mut buf = Vec::new();
+        p.to_bincode(&mut buf)?;
+        let q = Positions::<f64>::from_bincode(buf.as_slice())?;
+        assert_eq!(p, q);
+        let inst = q.get_position(&btc_usd_swap).unwrap().instrument();
+        assert!(is_identical(inst, &btc_usd_swap));
+        assert_eq!(inst.lot_size(), Some("100"));
+        let inst = q.get_position(&eth_usdt_quanto).unwrap().instrument();
+        assert!(is_identical(inst, &eth_usdt_quanto));
+        Ok(())
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn ndjson() -> anyhow::Result<()> {
+        use rust_decimal_macros::dec;
+
+        let usdt = Asset::usdt();
         let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
             .unwrap()
             .prefer_reversed(true);
-        let eth_btc_swap =
-            Instrument::try_new("SWAP:ETH-BTC-SWAP", &Asset::eth(), &Asset::btc()).unwrap();
+        let btc_usdt_swap =
+            Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
         let mut p = Positions::default();
-        p += (Decimal::from(-16000), &usdt);
-        p += (Decimal::from(1), &btc);
-        p += Reversed((Decimal::from(16000), Decimal::from(-16000), &btc_usd_swap));
-        p += (Decimal::from(0.067), Decimal::from(-21.5), &eth_btc_swap);
-        p += (
-            Decimal::from(16001),
-            Decimal::from(-1.5),
-            Decimal::from(-2.7),
-            &btc_usdt_swap,
-        );
-        let insts = p.as_expr().instruments(&Asset::ETH).collect::<Vec<_>>();
-        let usdt_eth = Instrument::spot(&Asset::USDT, &Asset::ETH);
-        let btc_eth = Instrument::spot(&Asset::BTC, &Asset::ETH);
-        assert_eq!(insts.len(), 5);
-        for inst in [
-            &btc_usd_swap,
-            &btc_usdt_swap,
-            &eth_btc_swap,
-            &usdt_eth,
-            &btc_eth,
-        ] {
-            assert!(insts.contains(inst));
-        }
+        p += (dec!(-16000), &usdt);
+        p += Reversed((dec!(16000), dec!(-16000), &btc_usd_swap));
+        p += (dec!(16001), dec!(-1.5), dec!(-2.7), &btc_usdt_swap);
+        p.charge_fee(dec!(1.2), &usdt);
+        let mut buf = Vec::new();
+        p.to_ndjson(&mut buf)?;
+        let s = String::from_utf8(buf)?;
+        println!("{s}");
+        assert_eq!(s.lines().count(), 5);
+        let q = Positions::from_ndjson(s.as_bytes())?;
+        assert_eq!(p, q);
+        assert!(q
+            .get_position(&btc_usd_swap)
+            .unwrap()
+            .instrument()
+            .is_prefer_reversed());
+        Ok(())
     }
 
     #[test]
-    fn eval_expr() {
+    fn snapshot_diff() {
         let btc = Asset::btc();
         let usdt = Asset::usdt();
         let btc_usdt_swap =
             Instrument::try_new("SWAP:BTC-USDT-SWAP", &Asset::btc(), &Asset::usdt()).unwrap();
-        let btc_usd_swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc())
-            .unwrap()
-            .prefer_reversed(true);
         let eth_btc_swap =
             Instrument::try_new("SWAP:ETH-BTC-SWAP", &Asset::eth(), &Asset::btc()).unwrap();
-        let mut p = Positions::default();
-        #[cfg(feature = "std")]
-        println!("{}", p.as_expr());
-        p += (Decimal::from(-16000), &usdt);
-        #[cfg(feature = "std")]
-        println!("{}", p.as_expr());
-        p += (Decimal::from(1), &btc);
-        #[cfg(feature = "std")]
-        println!("{}", p.as_expr());
-        p += Reversed((Decimal::from(16000), Decimal::from(-16000), &btc_usd_swap));
-        #[cfg(feature = "std")]
-        println!("{}", p.as_expr());
-        p += (Decimal::from(0.067), Decimal::from(-21.5), &eth_btc_swap);
-        #[cfg(feature = "std")]
-        println!("{}", p.as_expr());
-        p += (
-            Decimal::from(16001),
-            Decimal::from(-1.5),
-            Decimal::from(-2.7),
-            &btc_usdt_swap,
-        );
-        let expr = p.as_expr();
-        #[cfg(feature = "std")]
-        println!("{}", expr);
-        let prices = HashMap::from([
-            (eth_btc_swap.as_symbol().clone(), Decimal::from(0.059)),
-            (btc_usd_swap.as_symbol().clone(), Decimal::from(17000)),
-            (btc_usdt_swap.as_symbol().clone(), Decimal::from(17002)),
-            (Symbol::spot(&btc, &usdt), Decimal::from(17000)),
-        ]);
-        #[cfg(feature = "std")]
-        for inst in expr.instruments(&Asset::USDT) {
-            println!("{inst}");
+        let eth_usdt_swap =
+            Instrument::try_new("SWAP:ETH-USDT-SWAP", &Asset::eth(), &Asset::usdt()).unwrap();
+        let mut before = Positions::default();
+        before += (Decimal::from(-16000), &usdt);
+        before += (Decimal::from(1), &btc);
+        before += (Decimal::from(16001), Decimal::from(-1.5), &btc_usdt_swap);
+        before += (Decimal::from(0.067), Decimal::from(-21.5), &eth_btc_swap);
+        let mut after = before.clone();
+        assert!(diff_snapshots(&before, &after).is_empty());
+        after += (Decimal::from(100), &usdt);
+        after += (Decimal::from(16001), Decimal::from(1.5), &btc_usdt_swap);
+        after += (Decimal::from(1200), Decimal::from(1), &eth_usdt_swap);
+        after.extract(|inst, _| *inst == eth_btc_swap);
+        let diff = diff_snapshots(&before, &after);
+        assert_eq!(diff.values().len(), 1);
+        assert_eq!(diff.values().get(&usdt), Some(&Decimal::from(100)));
+        assert_eq!(diff.added().len(), 1);
+        assert_eq!(diff.added()[0].instrument(), &eth_usdt_swap);
+        assert_eq!(diff.removed().len(), 1);
+        assert_eq!(diff.removed()[0].instrument(), &eth_btc_swap);
+        assert_eq!(diff.changed().len(), 1);
+        assert_eq!(diff.changed()[0].1.size(), Decimal::from(0));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_position_meta() -> anyhow::Result<()> {
+        use rust_decimal_macros::dec;
+
+        let inst = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::usd(), &Asset::btc()).unwrap();
+        let p = inst.position((dec!(1.4), dec!(2)));
+        let s = serde_json::to_string(&p)?;
+        assert!(!s.contains("meta"));
+        let q = p.clone().with_meta(42u64);
+        let s = serde_json::to_string(&q)?;
+        assert!(s.contains(r#""meta":42"#));
+        let q: Position<rust_decimal::Decimal, u64> = serde_json::from_str(&s)?;
+        assert_eq!(*q.meta(), 42);
+        let q: Position<rust_decimal::Decimal, u64> =
+            serde_json::from_str(&serde_json::to_string(&p)?)?;
+        assert_eq!(*q.meta(), 0);
+        let s = serde_json::to_string(&q)?;
+        assert!(s.contains(r#""meta":0"#));
+
+        #[derive(Serialize)]
+        struct Tag {
+            name: &'static str,
         }
-        let ans = expr.eval(&Asset::USDT, &prices).unwrap().set_precision(1);
-        #[cfg(feature = "std")]
-        println!("{ans}");
-        assert_eq!(ans, Decimal::from(1419.8).set_precision(1));
+        let q = p.with_meta(Tag { name: "trend" });
+        let s = serde_json::to_string(&q)?;
+        assert!(s.contains(r#""meta":{"name":"trend"}"#));
+        Ok(())
     }
 
     #[cfg(feature = "serde")]
@@ -1008,6 +5319,8 @@ mod tests {
         };
         let positoins = Positions {
             values: HashMap::from([(inst.quote().clone(), sv)]),
+            fees: HashMap::default(),
+            ledger: None,
         };
         let s = serde_json::to_string(&positoins)?;
         #[cfg(feature = "std")]
@@ -1015,4 +5328,48 @@ mod tests {
         assert!(!s.is_empty());
         Ok(())
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_positions_compact() -> anyhow::Result<()> {
+        use rust_decimal_macros::dec;
+
+        let swap = Instrument::try_new("SWAP:BTC-USD-SWAP", &Asset::USD, &Asset::BTC)
+            .unwrap()
+            .prefer_reversed(true);
+        let spot = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let mut p = Asset::USDT.value(dec!(100));
+        p += Reversed((dec!(16000), dec!(100), dec!(0.5), &swap));
+        p += (dec!(16000), dec!(1), &spot);
+        p.insert_value(dec!(0), &Asset::ETH);
+        p.charge_fee(dec!(0.1), &Asset::USDT);
+        let value = serde_json::to_value(&p)?;
+        assert_eq!(value["positions"].as_array().map(|a| a.len()), Some(2));
+        assert_eq!(value["values"].as_array().map(|a| a.len()), Some(3));
+        let q: Positions<rust_decimal::Decimal> = serde_json::from_value(value)?;
+        assert_eq!(p, q);
+        assert!(q.values.contains_key(&Asset::ETH));
+        assert!(q.values[&Asset::ETH].is_empty());
+
+        let nested = serde_json::json!({
+            "values": {
+                "USDT": {
+                    "value": "100",
+                    "positions": {
+                        "BTC-USDT": {
+                            "instrument": serde_json::to_value(&spot)?,
+                            "price": "16000",
+                            "size": "1",
+                            "value": "0",
+                        },
+                    },
+                },
+            },
+        });
+        let q: Positions<rust_decimal::Decimal> = serde_json::from_value(nested)?;
+        let mut expected = Asset::USDT.value(dec!(100));
+        expected += (dec!(16000), dec!(1), &spot);
+        assert_eq!(q, expected);
+        Ok(())
+    }
 }
@@ -0,0 +1,134 @@
+use crate::{cross_rate, Asset, HashMap, Instrument, PositionNum, Symbol};
+
+/// A source of prices, e.g. a map of the last prices, a live oracle or a cache.
+pub trait PriceProvider<T> {
+    /// Get the price of the instrument.
+    /// Return [`None`] if the price is missing.
+    fn price_of(&self, instrument: &Instrument) -> Option<T>;
+
+    /// Get the price of the `base-quote` spot,
+    /// which is used for the synthetic pairs between assets.
+    /// Return [`None`] if the price is missing.
+    fn spot_price(&self, base: &Asset, quote: &Asset) -> Option<T> {
+        self.price_of(&Instrument::spot(base, quote))
+    }
+}
+
+impl<T: Clone> PriceProvider<T> for HashMap<Symbol, T> {
+    fn price_of(&self, instrument: &Instrument) -> Option<T> {
+        self.get(instrument.as_symbol()).cloned()
+    }
+
+    fn spot_price(&self, base: &Asset, quote: &Asset) -> Option<T> {
+        self.get(&Symbol::spot(base, quote)).cloned()
+    }
+}
+
+impl<T: Clone> PriceProvider<T> for HashMap<Instrument, T> {
+    fn price_of(&self, instrument: &Instrument) -> Option<T> {
+        self.get(instrument).cloned()
+    }
+
+    fn spot_price(&self, base: &Asset, quote: &Asset) -> Option<T> {
+        self.get(&Symbol::spot(base, quote)).cloned()
+    }
+}
+
+impl<T, P> PriceProvider<T> for &P
+where
+    P: PriceProvider<T> + ?Sized,
+{
+    fn price_of(&self, instrument: &Instrument) -> Option<T> {
+        (**self).price_of(instrument)
+    }
+
+    fn spot_price(&self, base: &Asset, quote: &Asset) -> Option<T> {
+        (**self).spot_price(base, quote)
+    }
+}
+
+/// Prices keyed by [`Symbol`] where a missing spot falls back to
+/// the reciprocal of its reversed spot (e.g. `USDT-BTC` for `BTC-USDT`).
+pub(crate) struct WithReversedSpots<'a, T>(pub(crate) &'a HashMap<Symbol, T>);
+
+impl<'a, T: PositionNum> PriceProvider<T> for WithReversedSpots<'a, T> {
+    fn price_of(&self, instrument: &Instrument) -> Option<T> {
+        match self.0.get(instrument.as_symbol()) {
+            Some(price) => Some(price.clone()),
+            None => {
+                let (base, quote) = instrument.as_symbol().as_spot()?;
+                self.spot_price(base, quote)
+            }
+        }
+    }
+
+    fn spot_price(&self, base: &Asset, quote: &Asset) -> Option<T> {
+        cross_rate(self.0, base, quote, quote)
+    }
+}
+
+/// A fallback chain of prices keyed by [`Symbol`],
+/// where each price is taken from the first source that has it.
+pub(crate) struct Layered<'a, T>(pub(crate) &'a [&'a HashMap<Symbol, T>]);
+
+impl<'a, T: Clone> PriceProvider<T> for Layered<'a, T> {
+    fn price_of(&self, instrument: &Instrument) -> Option<T> {
+        self.0
+            .iter()
+            .find_map(|prices| prices.get(instrument.as_symbol()))
+            .cloned()
+    }
+
+    fn spot_price(&self, base: &Asset, quote: &Asset) -> Option<T> {
+        let symbol = Symbol::spot(base, quote);
+        self.0
+            .iter()
+            .find_map(|prices| prices.get(&symbol))
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Positions;
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    /// A provider quoting every spot of `USDT` at a fixed price.
+    struct Flat(Decimal);
+
+    impl PriceProvider<Decimal> for Flat {
+        fn price_of(&self, instrument: &Instrument) -> Option<Decimal> {
+            (instrument.quote() == &Asset::USDT).then_some(self.0)
+        }
+    }
+
+    #[test]
+    fn custom_provider() {
+        let btc_usdt = Instrument::spot(&Asset::BTC, &Asset::USDT);
+        let mut p = Positions::default();
+        p += (dec!(20000), dec!(1), &btc_usdt);
+        p += (dec!(1), &Asset::ETH);
+        let usdt = Asset::USDT;
+        assert_eq!(
+            p.as_expr().eval(&usdt, &Flat(dec!(21000))),
+            Some(dec!(22000))
+        );
+        assert_eq!(p.as_tree(&usdt).eval(&Flat(dec!(21000))), Some(dec!(22000)));
+        let symbols = HashMap::from([
+            (btc_usdt.as_symbol().clone(), dec!(21000)),
+            (Symbol::spot(&Asset::ETH, &usdt), dec!(1000)),
+        ]);
+        let instruments: HashMap<Instrument, Decimal> = symbols
+            .iter()
+            .map(|(symbol, price)| {
+                let (base, quote) = symbol.as_spot().unwrap();
+                (Instrument::spot(base, quote), *price)
+            })
+            .collect();
+        assert_eq!(p.as_expr().eval(&usdt, &symbols), Some(dec!(2000)));
+        assert_eq!(p.as_expr().eval(&usdt, &instruments), Some(dec!(2000)));
+        assert_eq!(p.as_tree(&usdt).eval(&&instruments), Some(dec!(2000)));
+    }
+}
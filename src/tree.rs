@@ -1,4 +1,4 @@
-use crate::{Asset, HashMap, Instrument, Position, PositionNum};
+use crate::{price::PriceProvider, Asset, HashMap, Instrument, Position, PositionNum, Symbol};
 use alloc::{boxed::Box, fmt};
 
 /// Position Tree.
@@ -23,25 +23,97 @@ where
         children.chain(pairs).chain(positions)
     }
 
+    /// Create a wrapper displaying the tree with indentation showing its hierarchy.
+    pub fn display_tree(&self) -> DisplayTree<'_, 'a, T> {
+        DisplayTree(self)
+    }
+
+    /// Remove the position of the given instrument from the tree.
+    /// Return [`None`] if there is no such position.
+    pub fn remove_position(&mut self, instrument: &Instrument) -> Option<&'a Position<T>> {
+        self.positions.remove(instrument)
+    }
+
+    /// Remove the child tree of the given asset.
+    /// Return [`None`] if there is no such child.
+    pub fn remove_child(&mut self, asset: &Asset) -> Option<PositionTree<'a, T>> {
+        let inst = self
+            .children
+            .iter()
+            .find(|(_, t)| t.asset == asset)
+            .map(|(inst, _)| inst.clone())?;
+        self.children.remove(&inst)
+    }
+
     /// Evaluate the position tree with the given prices.
+    /// The prices of the `index-quote` spots are required for quanto instruments.
     /// Return `None` if there are missing prcies.
-    pub fn eval(&self, prices: &HashMap<Instrument, T>) -> Option<T> {
+    pub fn eval(&self, prices: &impl PriceProvider<T>) -> Option<T> {
+        self.try_eval(prices).ok()
+    }
+
+    /// Evaluate the position tree with the given prices,
+    /// the same as [`PositionTree::eval`] but reporting the first missing price.
+    pub fn try_eval(&self, prices: &impl PriceProvider<T>) -> Result<T, MissingPrice> {
+        let get = |inst: &Instrument| prices.price_of(inst).ok_or_else(|| MissingPrice::new(inst));
         let children = self
             .children
             .iter()
             .map(|(inst, t)| {
-                let mut value = t.eval(prices)?;
-                value *= prices.get(inst)?;
-                Some(value)
+                let mut value = t.try_eval(prices)?;
+                value *= get(inst)?;
+                Ok(value)
             })
-            .try_fold(T::zero(), |acc, x| Some(acc + x?))?;
+            .try_fold(T::zero(), |acc, x| x.map(|x| acc + x))?;
         let mut ans = self
             .positions
             .iter()
-            .map(|(inst, p)| Some(p.closed(prices.get(*inst)?)))
-            .try_fold(children, |acc, x| Some(acc + x?))?;
+            .map(|(inst, p)| {
+                let price = get(inst)?;
+                match inst.quanto_index() {
+                    Some(index) => {
+                        let rate = get(&Instrument::spot(index, inst.quote()))?;
+                        Ok(p.closed_with_rate(&price, &rate))
+                    }
+                    None => Ok(p.closed(&price)),
+                }
+            })
+            .try_fold(children, |acc, x| x.map(|x| acc + x))?;
         ans += &self.value;
-        Some(ans)
+        Ok(ans)
+    }
+
+    /// Evaluate the position tree with the given prices, returning the gross value
+    /// (the sum of the absolute values of the legs) together with the net value
+    /// (the same as [`PositionTree::eval`]), i.e. `(gross, net)`.
+    ///
+    /// The legs are the value, the closed values of the positions and the legs of
+    /// the children (converted with the absolute prices of their pairs).
+    /// Return `None` if there are missing prcies.
+    pub fn eval_gross_net(&self, prices: &impl PriceProvider<T>) -> Option<(T, T)> {
+        let mut gross = self.value.abs();
+        let mut net = self.value.clone();
+        for (inst, t) in self.children.iter() {
+            let price = prices.price_of(inst)?;
+            let (mut g, mut n) = t.eval_gross_net(prices)?;
+            g *= price.abs();
+            n *= price;
+            gross += g;
+            net += n;
+        }
+        for (inst, p) in self.positions.iter() {
+            let price = prices.price_of(inst)?;
+            let value = match inst.quanto_index() {
+                Some(index) => {
+                    let rate = prices.spot_price(index, inst.quote())?;
+                    p.closed_with_rate(&price, &rate)
+                }
+                None => p.closed(&price),
+            };
+            gross += value.abs();
+            net += value;
+        }
+        Some((gross, net))
     }
 
     /// Evaluate the position tree with the result price of the given function.
@@ -71,6 +143,73 @@ where
     }
 }
 
+/// The error of missing the price of an instrument, see [`PositionTree::try_eval`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "thiserror", derive(thiserror::Error))]
+#[cfg_attr(feature = "thiserror", error("missing price of {symbol}"))]
+pub struct MissingPrice {
+    /// The symbol of the instrument.
+    pub symbol: Symbol,
+    /// The base asset of the instrument.
+    pub base: Asset,
+    /// The quote asset of the instrument.
+    pub quote: Asset,
+}
+
+impl MissingPrice {
+    pub(crate) fn new(instrument: &Instrument) -> Self {
+        Self {
+            symbol: instrument.as_symbol().clone(),
+            base: instrument.base().clone(),
+            quote: instrument.quote().clone(),
+        }
+    }
+}
+
+#[cfg(not(feature = "thiserror"))]
+impl fmt::Display for MissingPrice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "missing price of {}", self.symbol)
+    }
+}
+
+/// A wrapper of [`PositionTree`] displaying the hierarchy with indentation.
+#[derive(Debug)]
+pub struct DisplayTree<'t, 'a, T>(&'t PositionTree<'a, T>);
+
+impl<'t, 'a, T> DisplayTree<'t, 'a, T>
+where
+    T: fmt::Display + PositionNum,
+{
+    const INDENT: &'static str = "    ";
+
+    fn write_tree(
+        f: &mut fmt::Formatter<'_>,
+        tree: &PositionTree<'a, T>,
+        depth: usize,
+    ) -> fmt::Result {
+        let indent = Self::INDENT.repeat(depth);
+        writeln!(f, "{indent}{} => {} {}", tree.asset, tree.value, tree.asset)?;
+        for (inst, p) in tree.positions.iter() {
+            writeln!(f, "{indent}{}{inst} => {p}", Self::INDENT)?;
+        }
+        for (inst, child) in tree.children.iter() {
+            writeln!(f, "{indent}{}[{inst}]", Self::INDENT)?;
+            Self::write_tree(f, child, depth + 2)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'t, 'a, T> fmt::Display for DisplayTree<'t, 'a, T>
+where
+    T: fmt::Display + PositionNum,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Self::write_tree(f, self.0, 0)
+    }
+}
+
 pub(super) fn write_position<T>(
     f: &mut fmt::Formatter<'_>,
     price: &T,
@@ -82,12 +221,9 @@ where
 {
     let asset = inst.base();
     if inst.is_prefer_reversed() {
-        if price.is_zero() {
-            write!(f, "(Nan, {} {asset})*", -size.clone())
-        } else {
-            let mut real_price = T::one();
-            real_price /= price;
-            write!(f, "({}, {} {asset})*", real_price, -size.clone())
+        match price.checked_reciprocal() {
+            Some(real_price) => write!(f, "({}, {} {asset})*", real_price, -size.clone()),
+            None => write!(f, "(Nan, {} {asset})*", -size.clone()),
         }
     } else {
         write!(f, "({price}, {size} {asset})")